@@ -85,14 +85,13 @@ fn parse_tool_calls(text: &str) -> Vec<ToolCall> {
     let mut tool_calls = Vec::new();
 
     // Extract JSON objects that may span multiple lines
-    let mut chars = text.chars().peekable();
     let mut current_json = String::new();
     let mut brace_depth = 0;
     let mut in_string = false;
     let mut escape_next = false;
     let mut capturing = false;
 
-    while let Some(ch) = chars.next() {
+    for ch in text.chars() {
         if escape_next {
             if capturing {
                 current_json.push(ch);
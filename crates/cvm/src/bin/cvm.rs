@@ -1,6 +1,538 @@
-//! Context VM CLI binary
+//! Context VM CLI binary - inspect and talk to ContextVM servers from the shell
 
-fn main() {
-    println!("Context VM - Nostr Context VM Implementation");
-    println!("Ready for TypeScript SDK port");
+use anyhow::Context;
+use clap::{Parser, Subcommand};
+use cvm::constants::{SERVER_ANNOUNCEMENT_KIND, TOOLS_LIST_KIND};
+use cvm::signer::keystore;
+use cvm::{
+    signer, DeliveryMode, EncryptionMode, Keys, Keystore, NostrClientTransport, NostrClientTransportConfig,
+    NostrServerTransport, NostrServerTransportConfig, RelayPool, ServerAnnouncement, ServerInfo,
+};
+use nostr_sdk::nips::nip19::ToBech32;
+use nostr_sdk::prelude::{Filter, Kind, PublicKey};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Nostr relay URLs
+    #[arg(long, global = true, default_value = "wss://relay.damus.io")]
+    relay: Vec<String>,
+
+    /// Relays among `--relay` that require NIP-42 authentication
+    #[arg(long, global = true)]
+    auth_relay: Vec<String>,
+
+    /// Nostr private key (nsec or hex format); a throwaway key is used if omitted
+    #[arg(long, global = true, env = "CVM_PRIVATE_KEY", hide_env_values = true)]
+    private_key: Option<String>,
+
+    /// Encryption mode: optional, required, disabled
+    #[arg(long, global = true, default_value = "optional", value_parser = parse_encryption)]
+    encryption: EncryptionMode,
+
+    /// NIP-13 difficulty inbound requests must meet (announce and listen)
+    #[arg(long, global = true)]
+    min_pow: Option<u8>,
+
+    /// Queue messages no relay accepts and retry them, keeping notifications in this directory (call)
+    #[arg(long, global = true)]
+    outbox: Option<PathBuf>,
+
+    /// Serve Prometheus metrics at http://127.0.0.1:<port>/metrics while running
+    #[arg(long, global = true)]
+    metrics_port: Option<u16>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Manage keys in the keystore (`$CVM_KEYSTORE`, default `~/.config/cvm/keys`)
+    Key {
+        #[command(subcommand)]
+        command: KeyCommand,
+    },
+
+    /// Same as `key generate`, kept for existing scripts
+    #[command(hide = true)]
+    Keygen(GenerateArgs),
+
+    /// Publish a server announcement described by a TOML `ServerInfo` file
+    Announce {
+        /// TOML file with `name`, `version`, `about`, `picture` and `website`
+        info: PathBuf,
+
+        /// JSON file with an array of tools to publish alongside the announcement
+        #[arg(long)]
+        tools: Option<PathBuf>,
+    },
+
+    /// Withdraw this key's announcement and capability lists
+    Retract,
+
+    /// Print incoming requests addressed to this key as JSON lines
+    Listen {
+        /// TOML `ServerInfo` file used to answer `initialize`
+        #[arg(long)]
+        info: Option<PathBuf>,
+    },
+
+    /// Initialize a session with a server and call a method
+    Call {
+        /// Server public key (npub or hex)
+        server: String,
+
+        /// JSON-RPC method, e.g. `tools/list` or `tools/call`
+        method: String,
+
+        /// JSON-RPC params
+        #[arg(default_value = "{}")]
+        params: String,
+
+        /// Request timeout in seconds
+        #[arg(long, default_value_t = 30)]
+        timeout: u64,
+    },
+
+    /// List servers announced on the relays with their tools
+    Discover {
+        /// How long to wait for relays, in seconds
+        #[arg(long, default_value_t = 10)]
+        timeout: u64,
+    },
+}
+
+/// Keystore commands; secret keys are never printed
+#[derive(Subcommand, Debug)]
+enum KeyCommand {
+    /// Generate a new key
+    Generate(GenerateArgs),
+
+    /// Import a secret key (nsec, hex or ncryptsec) read from stdin
+    Import {
+        name: String,
+
+        /// Store the key unencrypted (the key file is still readable only by you)
+        #[arg(long)]
+        no_passphrase: bool,
+    },
+
+    /// Print a key as an ncryptsec encrypted with a new passphrase
+    Export { name: String },
+
+    /// Replace a key with a new one, keeping the old key file as a backup
+    Rotate { name: String },
+
+    /// List stored keys with their public keys
+    List,
+}
+
+#[derive(clap::Args, Debug)]
+struct GenerateArgs {
+    name: String,
+
+    /// Store the key unencrypted (the key file is still readable only by you)
+    #[arg(long)]
+    no_passphrase: bool,
+}
+
+fn parse_encryption(mode: &str) -> Result<EncryptionMode, String> {
+    match mode {
+        "optional" => Ok(EncryptionMode::Optional),
+        "required" => Ok(EncryptionMode::Required),
+        "disabled" => Ok(EncryptionMode::Disabled),
+        _ => Err(format!("invalid encryption mode: {}", mode)),
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .with_writer(std::io::stderr)
+        .init();
+
+    let args = Args::parse();
+
+    let keys = match &args.private_key {
+        Some(sk) => signer::from_sk(sk).context("Invalid private key")?,
+        None => signer::generate(),
+    };
+
+    // Kept alive until the command finishes
+    let _exporter = match args.metrics_port {
+        Some(port) => Some(cvm::metrics::serve(([127, 0, 0, 1], port).into()).await?),
+        None => None,
+    };
+
+    match args.command {
+        Command::Key { command } => key(command),
+        Command::Keygen(generate) => key(KeyCommand::Generate(generate)),
+        Command::Announce { ref info, ref tools } => {
+            announce(&args, keys, info, tools.as_ref()).await
+        }
+        Command::Retract => retract(&args, keys).await,
+        Command::Listen { ref info } => listen(&args, keys, info.as_ref()).await,
+        Command::Call {
+            ref server,
+            ref method,
+            ref params,
+            timeout,
+        } => call(&args, keys, server, method, params, timeout).await,
+        Command::Discover { timeout } => discover(&args, keys, timeout).await,
+    }
+}
+
+fn key(command: KeyCommand) -> anyhow::Result<()> {
+    let keystore = Keystore::open_default()?;
+
+    match command {
+        KeyCommand::Generate(GenerateArgs { name, no_passphrase }) => {
+            let passphrase = (!no_passphrase).then(new_passphrase).transpose()?;
+            let pubkey = keystore.generate(&name, passphrase.as_deref())?;
+            print_stored(&keystore, &name, &pubkey)?;
+        }
+        KeyCommand::Import { name, no_passphrase } => {
+            let secret = read_secret("Secret key: ")?;
+            let passphrase = if secret.trim().starts_with("ncryptsec1") {
+                Some(passphrase("Passphrase of the imported key: ")?)
+            } else {
+                (!no_passphrase).then(new_passphrase).transpose()?
+            };
+            let pubkey = keystore.import(&name, &secret, passphrase.as_deref())?;
+            print_stored(&keystore, &name, &pubkey)?;
+        }
+        KeyCommand::Export { name } => {
+            let current = stored_passphrase(&keystore, &name)?;
+            // Always prompted for, so an export never silently reuses `$CVM_KEY_PASSPHRASE`
+            eprintln!("Choose a passphrase for the exported key");
+            let export = prompt_new_passphrase()?;
+            println!("{}", keystore.export(&name, current.as_deref(), &export)?);
+        }
+        KeyCommand::Rotate { name } => {
+            let current = stored_passphrase(&keystore, &name)?;
+            let (old, new) = keystore.rotate(&name, current.as_deref())?;
+            println!("Rotated {}", name);
+            println!("old npub: {}", old.to_bech32()?);
+            println!("new npub: {}", new.to_bech32()?);
+        }
+        KeyCommand::List => {
+            for (name, pubkey) in keystore.list()? {
+                let npub = match pubkey {
+                    Some(pubkey) => pubkey.to_bech32()?,
+                    None => "(unknown public key)".to_string(),
+                };
+                println!("{:<20} {}", name, npub);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn print_stored(keystore: &Keystore, name: &str, pubkey: &PublicKey) -> anyhow::Result<()> {
+    println!("Stored {} in {}", name, keystore.dir().display());
+    println!("npub: {}", pubkey.to_bech32()?);
+    println!("hex:  {}", pubkey.to_hex());
+    println!("Use it with --private-key keystore:{}", name);
+    Ok(())
+}
+
+/// Passphrase from `$CVM_KEY_PASSPHRASE`, or prompted for without echo
+fn passphrase(prompt: &str) -> anyhow::Result<String> {
+    if let Ok(passphrase) = std::env::var(keystore::PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+    Ok(rpassword::prompt_password(prompt)?)
+}
+
+/// New passphrase from `$CVM_KEY_PASSPHRASE`, or asked for twice
+fn new_passphrase() -> anyhow::Result<String> {
+    if let Ok(passphrase) = std::env::var(keystore::PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+    prompt_new_passphrase()
+}
+
+/// Ask for a new passphrase twice, whatever the environment holds
+fn prompt_new_passphrase() -> anyhow::Result<String> {
+    let passphrase = rpassword::prompt_password("New passphrase: ")?;
+    if passphrase.is_empty() {
+        anyhow::bail!("Empty passphrase: use --no-passphrase to store the key unencrypted");
+    }
+    if rpassword::prompt_password("Repeat passphrase: ")? != passphrase {
+        anyhow::bail!("Passphrases do not match");
+    }
+    Ok(passphrase)
+}
+
+/// Passphrase of a stored key, if it is encrypted
+fn stored_passphrase(keystore: &Keystore, name: &str) -> anyhow::Result<Option<String>> {
+    if keystore.is_encrypted(name)? {
+        Ok(Some(passphrase(&format!("Passphrase of {}: ", name))?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Read a secret from the terminal without echo, or from piped stdin
+fn read_secret(prompt: &str) -> anyhow::Result<String> {
+    if std::io::stdin().is_terminal() {
+        return Ok(rpassword::prompt_password(prompt)?);
+    }
+
+    let mut secret = String::new();
+    std::io::stdin().read_line(&mut secret)?;
+    Ok(secret.trim().to_string())
+}
+
+fn load_server_info(path: &PathBuf) -> anyhow::Result<ServerInfo> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    toml::from_str(&content).with_context(|| format!("Invalid server info in {}", path.display()))
+}
+
+fn server_config(args: &Args, server_info: Option<ServerInfo>) -> NostrServerTransportConfig {
+    NostrServerTransportConfig {
+        relay_urls: args.relay.clone(),
+        auth_relay_urls: args.auth_relay.clone(),
+        encryption_mode: args.encryption,
+        server_info,
+        min_pow: args.min_pow,
+        ..Default::default()
+    }
+}
+
+async fn announce(
+    args: &Args,
+    keys: Keys,
+    info: &PathBuf,
+    tools: Option<&PathBuf>,
+) -> anyhow::Result<()> {
+    if args.private_key.is_none() {
+        anyhow::bail!("Announcing requires --private-key so the server keeps its identity");
+    }
+
+    let server_info = load_server_info(info)?;
+    let tools: Option<Vec<Value>> = match tools {
+        Some(path) => {
+            let content = std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            Some(serde_json::from_str(&content).context("Tools file must be a JSON array")?)
+        }
+        None => None,
+    };
+
+    // Without an `announce_interval` the one-off announcement neither expires nor looks offline
+    let mut config = server_config(args, Some(server_info));
+    if tools.is_some() {
+        config.capabilities = json!({ "tools": {} });
+    }
+
+    let pubkey = keys.public_key();
+    let server = NostrServerTransport::new(keys, config).await?;
+    server.announce().await?;
+    if let Some(tools) = tools {
+        println!("Publishing {} tools", tools.len());
+        server.publish_tools(tools).await?;
+    }
+
+    println!("Announced {}", pubkey.to_bech32()?);
+
+    Ok(())
+}
+
+async fn retract(args: &Args, keys: Keys) -> anyhow::Result<()> {
+    if args.private_key.is_none() {
+        anyhow::bail!("Retracting requires --private-key of the announced server");
+    }
+
+    let pubkey = keys.public_key();
+    let server = NostrServerTransport::new(keys, server_config(args, None)).await?;
+    server.retract().await?;
+
+    println!("Retracted {}", pubkey.to_bech32()?);
+
+    Ok(())
+}
+
+async fn listen(args: &Args, keys: Keys, info: Option<&PathBuf>) -> anyhow::Result<()> {
+    let server_info = info.map(load_server_info).transpose()?;
+
+    eprintln!("Listening as {}", keys.public_key().to_bech32()?);
+
+    let server = NostrServerTransport::new(keys, server_config(args, server_info)).await?;
+    let mut incoming = server.start().await?;
+
+    while let Some(message) = incoming.recv().await {
+        let content: Value =
+            serde_json::from_str(&message.content).unwrap_or(Value::String(message.content.clone()));
+
+        println!(
+            "{}",
+            json!({
+                "from": message.sender_pubkey.to_bech32()?,
+                "event_id": message.event_id.to_hex(),
+                "encrypted": message.is_encrypted,
+                "message": content,
+            })
+        );
+
+        // Complete the handshake so clients can go on to send real requests
+        if content["method"] == "initialize" {
+            let response = json!({
+                "jsonrpc": "2.0",
+                "id": content["id"],
+                "result": server.initialize_result(),
+            });
+            server
+                .send_response(&message.sender_pubkey, response.to_string(), &message.event_id)
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn call(
+    args: &Args,
+    keys: Keys,
+    server: &str,
+    method: &str,
+    params: &str,
+    timeout: u64,
+) -> anyhow::Result<()> {
+    let server_pubkey = PublicKey::parse(server).context("Invalid server public key")?;
+    let params: Value = serde_json::from_str(params).context("Params must be valid JSON")?;
+
+    let config = NostrClientTransportConfig {
+        relay_urls: args.relay.clone(),
+        auth_relay_urls: args.auth_relay.clone(),
+        encryption_mode: args.encryption,
+        request_timeout: Duration::from_secs(timeout),
+        delivery_mode: if args.outbox.is_some() { DeliveryMode::Queued } else { DeliveryMode::FailFast },
+        outbox_dir: args.outbox.clone(),
+        ..Default::default()
+    };
+    let client = NostrClientTransport::new(keys, config).await?;
+    client.connect().await?;
+
+    // Learn whether the server can decrypt before choosing how to send
+    if let Err(e) = client.fetch_announcement(&server_pubkey).await {
+        tracing::warn!("Could not fetch server announcement: {}", e);
+    }
+
+    let initialize = json!({
+        "jsonrpc": "2.0",
+        "id": 0,
+        "method": "initialize",
+        "params": {
+            "protocolVersion": cvm::constants::MCP_PROTOCOL_VERSION,
+            "capabilities": {},
+            "clientInfo": { "name": "cvm", "version": env!("CARGO_PKG_VERSION") },
+        },
+    });
+    let response = client
+        .send_request(&server_pubkey, initialize.to_string())
+        .await?;
+
+    if method == "initialize" {
+        println!("{}", pretty(&response));
+        return Ok(());
+    }
+
+    let initialized = json!({ "jsonrpc": "2.0", "method": "notifications/initialized" });
+    client
+        .send_notification(&server_pubkey, initialized.to_string())
+        .await?;
+
+    let request = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+    let response = client.send_request(&server_pubkey, request.to_string()).await?;
+    println!("{}", pretty(&response));
+
+    Ok(())
+}
+
+async fn discover(args: &Args, keys: Keys, timeout: u64) -> anyhow::Result<()> {
+    let relay_pool = RelayPool::new(keys).await?;
+    relay_pool.require_auth(&args.auth_relay).await?;
+    relay_pool.connect(&args.relay).await?;
+
+    let filter = Filter::new().kinds([
+        Kind::from(SERVER_ANNOUNCEMENT_KIND),
+        Kind::from(TOOLS_LIST_KIND),
+    ]);
+    let events = relay_pool
+        .fetch_events(vec![filter], Duration::from_secs(timeout))
+        .await?;
+
+    // Events are sorted newest first, so the first of each kind per author wins
+    let mut announcements: Vec<ServerAnnouncement> = Vec::new();
+    let mut tools: HashMap<PublicKey, Vec<Value>> = HashMap::new();
+    for event in &events {
+        if event.kind == Kind::from(SERVER_ANNOUNCEMENT_KIND) {
+            if announcements.iter().all(|a| a.pubkey != event.pubkey) {
+                announcements.extend(ServerAnnouncement::from_event(event));
+            }
+        } else {
+            tools.entry(event.pubkey).or_insert_with(|| {
+                let list: Value = serde_json::from_str(&event.content).unwrap_or_default();
+                list["tools"].as_array().cloned().unwrap_or_default()
+            });
+        }
+    }
+
+    if announcements.is_empty() {
+        println!("No servers found");
+    }
+
+    for announcement in &announcements {
+        let info = &announcement.server_info;
+        println!(
+            "{} {}",
+            info.name.as_deref().unwrap_or("(unnamed)"),
+            info.version.as_deref().unwrap_or("")
+        );
+        println!("  pubkey:     {}", announcement.pubkey.to_bech32()?);
+        let status = match announcement.is_online() {
+            Some(true) => "online",
+            Some(false) => "offline",
+            None => "unknown",
+        };
+        println!("  status:     {}", status);
+        if let Some(about) = &info.about {
+            println!("  about:      {}", about);
+        }
+        println!(
+            "  encryption: {}",
+            if announcement.supports_encryption { "supported" } else { "not supported" }
+        );
+        if let Some(min_pow) = announcement.min_pow {
+            println!("  min pow:    {}", min_pow);
+        }
+        for tool in tools.get(&announcement.pubkey).into_iter().flatten() {
+            println!(
+                "  - {}: {}",
+                tool["name"].as_str().unwrap_or("?"),
+                tool["description"].as_str().unwrap_or("")
+            );
+        }
+        println!();
+    }
+
+    relay_pool.disconnect().await?;
+
+    Ok(())
+}
+
+fn pretty(json: &str) -> String {
+    serde_json::from_str::<Value>(json)
+        .and_then(|value| serde_json::to_string_pretty(&value))
+        .unwrap_or_else(|_| json.to_string())
 }
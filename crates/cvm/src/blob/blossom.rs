@@ -0,0 +1,223 @@
+//! Blossom blob server client
+//!
+//! Uploads use `PUT /upload` (BUD-02) authorized by a signed kind 24242 event in the
+//! `Authorization: Nostr <base64 event>` header (BUD-01). Blobs are downloaded from the
+//! descriptor's URL with a plain `GET`, reading no more than the descriptor's size.
+
+use super::{BlobDescriptor, BlobStore};
+use crate::core::constants::DEFAULT_MAX_BLOB_SIZE;
+use crate::core::error::{Error, Result};
+use async_trait::async_trait;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use nostr_sdk::prelude::*;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Kind of Blossom authorization events
+const AUTH_KIND: u16 = 24242;
+
+/// How long an upload authorization stays valid
+const AUTH_TTL: Duration = Duration::from_secs(300);
+
+/// Client of one Blossom server, signing uploads with a Nostr key
+#[derive(Clone)]
+pub struct BlossomClient {
+    server: String,
+    signer: Arc<dyn NostrSigner>,
+    http: reqwest::Client,
+    /// Largest blob fetched, in bytes
+    max_size: u64,
+}
+
+impl std::fmt::Debug for BlossomClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BlossomClient").field("server", &self.server).finish()
+    }
+}
+
+impl BlossomClient {
+    /// Client for the server at `server` (e.g. `https://blossom.example.com`)
+    pub fn new<T>(server: impl Into<String>, signer: T) -> Self
+    where
+        T: IntoNostrSigner,
+    {
+        Self {
+            server: server.into().trim_end_matches('/').to_string(),
+            signer: signer.into_nostr_signer(),
+            http: reqwest::Client::new(),
+            max_size: DEFAULT_MAX_BLOB_SIZE,
+        }
+    }
+
+    /// Refuse to fetch blobs larger than `max_size` bytes
+    pub fn with_max_size(mut self, max_size: u64) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    pub fn server(&self) -> &str {
+        &self.server
+    }
+
+    /// Download the body at `url`, failing as soon as it grows past `limit` bytes
+    async fn download(&self, url: reqwest::Url, limit: u64) -> Result<Vec<u8>> {
+        let mut response = self
+            .http
+            .get(url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| Error::Blob(e.to_string()))?;
+        if let Some(length) = response.content_length().filter(|length| *length > limit) {
+            return Err(Error::Blob(format!("Blob of {} bytes exceeds {} bytes", length, limit)));
+        }
+
+        let mut data = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(|e| Error::Blob(e.to_string()))? {
+            if (data.len() + chunk.len()) as u64 > limit {
+                return Err(Error::Blob(format!("Blob exceeds {} bytes", limit)));
+            }
+            data.extend_from_slice(&chunk);
+        }
+
+        Ok(data)
+    }
+
+    /// `Authorization` header value allowing the upload of one blob
+    async fn authorization(&self, sha256: &str) -> Result<String> {
+        let pubkey = self
+            .signer
+            .get_public_key()
+            .await
+            .map_err(|e| Error::Other(e.to_string()))?;
+        let expiration = Timestamp::from(Timestamp::now().as_u64() + AUTH_TTL.as_secs());
+
+        let unsigned = EventBuilder::new(Kind::from(AUTH_KIND), "Upload blob")
+            .tag(Tag::hashtag("upload"))
+            .tag(Tag::custom(TagKind::custom("x"), [sha256]))
+            .tag(Tag::expiration(expiration))
+            .build(pubkey);
+        let event = self
+            .signer
+            .sign_event(unsigned)
+            .await
+            .map_err(|e| Error::Other(e.to_string()))?;
+
+        Ok(format!("Nostr {}", BASE64.encode(event.as_json())))
+    }
+}
+
+#[async_trait]
+impl BlobStore for BlossomClient {
+    async fn upload(&self, data: Vec<u8>, mime_type: Option<&str>) -> Result<BlobDescriptor> {
+        let sha256 = super::sha256_hex(&data);
+        let authorization = self.authorization(&sha256).await?;
+
+        let response = self
+            .http
+            .put(format!("{}/upload", self.server))
+            .header("Authorization", authorization)
+            .header("Content-Type", mime_type.unwrap_or("application/octet-stream"))
+            .body(data)
+            .send()
+            .await
+            .map_err(|e| Error::Blob(e.to_string()))?;
+
+        if !response.status().is_success() {
+            // Blossom servers explain refusals in the `X-Reason` header
+            let reason = response
+                .headers()
+                .get("X-Reason")
+                .and_then(|reason| reason.to_str().ok())
+                .unwrap_or_default()
+                .to_string();
+            return Err(Error::Blob(format!("Upload refused ({}): {}", response.status(), reason)));
+        }
+
+        let descriptor: BlobDescriptor = response
+            .json()
+            .await
+            .map_err(|e| Error::Blob(format!("Invalid blob descriptor: {}", e)))?;
+        if !descriptor.sha256.eq_ignore_ascii_case(&sha256) {
+            return Err(Error::Blob(format!(
+                "Server stored {} instead of {}",
+                descriptor.sha256, sha256
+            )));
+        }
+
+        Ok(descriptor)
+    }
+
+    async fn fetch(&self, descriptor: &BlobDescriptor) -> Result<Vec<u8>> {
+        if descriptor.size > self.max_size {
+            return Err(Error::Blob(format!(
+                "Blob of {} bytes exceeds the limit of {} bytes",
+                descriptor.size, self.max_size
+            )));
+        }
+
+        // Only follow URLs that look like Blossom blob URLs, not arbitrary endpoints
+        let url = reqwest::Url::parse(&descriptor.url).map_err(|e| Error::Blob(e.to_string()))?;
+        let names_blob = url
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .is_some_and(|name| name.starts_with(&descriptor.sha256));
+        if url.scheme() != "https" || !names_blob {
+            return Err(Error::Blob(format!("Refusing to fetch blob from {}", descriptor.url)));
+        }
+
+        self.download(url, descriptor.size).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Answer every request with `body`, without a `Content-Length`
+    async fn serve(body: Vec<u8>) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let body = body.clone();
+                tokio::spawn(async move {
+                    let mut request = [0u8; 1024];
+                    let _ = stream.read(&mut request).await;
+                    let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\n").await;
+                    let _ = stream.write_all(&body).await;
+                    let _ = stream.shutdown().await;
+                });
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_fetch_size_limits() {
+        let client = BlossomClient::new("https://blossom.example.com", Keys::generate()).with_max_size(1024);
+        let data = vec![1u8; 512];
+        let sha256 = super::super::sha256_hex(&data);
+        let addr = serve(data.clone()).await;
+        let url = reqwest::Url::parse(&format!("http://{}/{}", addr, sha256)).unwrap();
+
+        assert_eq!(client.download(url.clone(), 512).await.unwrap(), data);
+        // A body longer than announced is cut off
+        assert!(client.download(url, 100).await.is_err());
+
+        // Descriptors over the limit are refused before any request
+        let descriptor = BlobDescriptor {
+            url: format!("https://blossom.example.com/{}", sha256),
+            sha256,
+            size: 2048,
+            mime_type: None,
+            uploaded: 0,
+        };
+        let error = client.fetch(&descriptor).await.unwrap_err();
+        assert!(error.to_string().contains("exceeds the limit"));
+    }
+}
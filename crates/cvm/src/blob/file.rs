@@ -0,0 +1,63 @@
+//! Blob store in a local directory
+
+use super::{sha256_hex, BlobDescriptor, BlobStore};
+use crate::core::error::{Error, Result};
+use async_trait::async_trait;
+use nostr_sdk::prelude::Timestamp;
+use std::path::{Path, PathBuf};
+
+/// Blobs stored as `<dir>/<sha256>`, with `file://` URLs
+///
+/// Blobs are always read from the store's own directory, whatever path a descriptor's
+/// URL names, so a peer cannot make us read other files.
+#[derive(Debug, Clone)]
+pub struct FileBlobStore {
+    dir: PathBuf,
+}
+
+impl FileBlobStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn blob_path(&self, sha256: &str) -> Result<PathBuf> {
+        let valid = sha256.len() == 64 && sha256.chars().all(|c| c.is_ascii_hexdigit());
+        if !valid {
+            return Err(Error::Blob(format!("Invalid blob hash {:?}", sha256)));
+        }
+        Ok(self.dir.join(sha256.to_ascii_lowercase()))
+    }
+}
+
+#[async_trait]
+impl BlobStore for FileBlobStore {
+    async fn upload(&self, data: Vec<u8>, mime_type: Option<&str>) -> Result<BlobDescriptor> {
+        let sha256 = sha256_hex(&data);
+        let path = self.blob_path(&sha256)?;
+
+        tokio::fs::create_dir_all(&self.dir).await?;
+        // Write then rename, so readers never see a partial blob
+        let tmp = path.with_extension("tmp");
+        tokio::fs::write(&tmp, &data).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+
+        Ok(BlobDescriptor {
+            url: format!("file://{}", path.display()),
+            sha256,
+            size: data.len() as u64,
+            mime_type: mime_type.map(str::to_string),
+            uploaded: Timestamp::now().as_u64(),
+        })
+    }
+
+    async fn fetch(&self, descriptor: &BlobDescriptor) -> Result<Vec<u8>> {
+        let path = self.blob_path(&descriptor.sha256)?;
+        tokio::fs::read(&path)
+            .await
+            .map_err(|e| Error::Blob(format!("Blob {} not found: {}", descriptor.sha256, e)))
+    }
+}
@@ -0,0 +1,258 @@
+//! Content-addressed blob offloading
+//!
+//! Binary payloads of MCP results, the base64 `data` of image and audio content and the
+//! `blob` of embedded resources, are too large to ride inside Nostr events. A server
+//! with a [`BlobStore`] uploads payloads above a size threshold and replaces them with an
+//! empty string plus a [`BlobDescriptor`] under `_meta["contextvm/blob"]`. A client with
+//! a store downloads the blob, checks its sha256 and puts the payload back.
+//!
+//! Only clients that can do so get references: they [`advertise`] the experimental
+//! `contextvm/blob` capability in `initialize`, and everyone else gets payloads inline.
+//!
+//! [`BlossomClient`] talks to a Blossom server (feature `blossom`); [`FileBlobStore`]
+//! keeps blobs in a local directory, for tests and single-machine setups.
+
+#[cfg(feature = "blossom")]
+mod blossom;
+mod file;
+
+#[cfg(feature = "blossom")]
+pub use blossom::BlossomClient;
+pub use file::FileBlobStore;
+
+use crate::core::error::{Error, Result};
+use async_trait::async_trait;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use nostr_sdk::hashes::{sha256::Hash as Sha256Hash, Hash};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// `_meta` key of blob references in MCP content
+pub const BLOB_META_KEY: &str = "contextvm/blob";
+
+/// A stored blob, as described by Blossom servers
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlobDescriptor {
+    /// Where the blob can be downloaded
+    pub url: String,
+    /// Hex sha256 of the blob
+    pub sha256: String,
+    /// Size in bytes
+    pub size: u64,
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub mime_type: Option<String>,
+    /// Unix time of the upload
+    #[serde(default)]
+    pub uploaded: u64,
+}
+
+/// Content-addressed storage for binary payloads
+#[async_trait]
+pub trait BlobStore: Send + Sync + std::fmt::Debug {
+    /// Store a blob and describe where it can be downloaded
+    async fn upload(&self, data: Vec<u8>, mime_type: Option<&str>) -> Result<BlobDescriptor>;
+
+    /// Download a blob; the caller verifies its hash
+    async fn fetch(&self, descriptor: &BlobDescriptor) -> Result<Vec<u8>>;
+}
+
+/// Add the `contextvm/blob` capability to an `initialize` request, so servers send
+/// blob references instead of inline payloads
+pub fn advertise(request: &mut Value) {
+    let Some(params) = request.get_mut("params").and_then(Value::as_object_mut) else {
+        return;
+    };
+    let capabilities = params
+        .entry("capabilities")
+        .or_insert_with(|| Value::Object(Map::new()));
+    let Some(capabilities) = capabilities.as_object_mut() else {
+        return;
+    };
+    let experimental = capabilities
+        .entry("experimental")
+        .or_insert_with(|| Value::Object(Map::new()));
+    if let Some(experimental) = experimental.as_object_mut() {
+        experimental.insert(BLOB_META_KEY.to_string(), Value::Object(Map::new()));
+    }
+}
+
+/// Whether client capabilities include `contextvm/blob`
+pub fn accepted(capabilities: &Value) -> bool {
+    capabilities["experimental"].get(BLOB_META_KEY).is_some()
+}
+
+/// Hex sha256 of a blob
+pub fn sha256_hex(data: &[u8]) -> String {
+    Sha256Hash::hash(data).to_string()
+}
+
+/// Check that a blob has the expected hash
+pub fn verify(data: &[u8], sha256: &str) -> Result<()> {
+    let actual = sha256_hex(data);
+    if actual.eq_ignore_ascii_case(sha256) {
+        Ok(())
+    } else {
+        Err(Error::Blob(format!("Hash mismatch: expected {}, got {}", sha256, actual)))
+    }
+}
+
+/// Upload the base64 payloads of a JSON-RPC message longer than `threshold` and replace
+/// them with blob references, returning how many were offloaded
+pub async fn offload(store: &dyn BlobStore, message: &mut Value, threshold: usize) -> Result<usize> {
+    let mut offloaded = 0;
+
+    for (object, field) in payloads(message) {
+        let Some(Value::String(data)) = object.get(field) else {
+            continue;
+        };
+        if data.len() <= threshold {
+            continue;
+        }
+
+        let bytes = BASE64
+            .decode(data)
+            .map_err(|e| Error::Blob(format!("Invalid base64 in `{}`: {}", field, e)))?;
+        let mime_type = object.get("mimeType").and_then(Value::as_str).map(str::to_string);
+        let descriptor = store.upload(bytes, mime_type.as_deref()).await?;
+
+        object.insert(field.to_string(), Value::String(String::new()));
+        let meta = object
+            .entry("_meta")
+            .or_insert_with(|| Value::Object(Map::new()));
+        if let Some(meta) = meta.as_object_mut() {
+            meta.insert(BLOB_META_KEY.to_string(), serde_json::to_value(&descriptor)?);
+        }
+        offloaded += 1;
+    }
+
+    Ok(offloaded)
+}
+
+/// Download the blobs referenced by a JSON-RPC message, verify them and put their
+/// payloads back, returning how many were restored
+pub async fn restore(store: &dyn BlobStore, message: &mut Value) -> Result<usize> {
+    let mut restored = 0;
+
+    for (object, field) in payloads(message) {
+        let Some(meta) = object.get_mut("_meta").and_then(Value::as_object_mut) else {
+            continue;
+        };
+        let Some(reference) = meta.remove(BLOB_META_KEY) else {
+            continue;
+        };
+        let meta_is_empty = meta.is_empty();
+
+        let descriptor: BlobDescriptor = serde_json::from_value(reference)?;
+        let data = store.fetch(&descriptor).await?;
+        if data.len() as u64 != descriptor.size {
+            return Err(Error::Blob(format!(
+                "Blob is {} bytes, expected {}",
+                data.len(),
+                descriptor.size
+            )));
+        }
+        verify(&data, &descriptor.sha256)?;
+
+        if meta_is_empty {
+            object.remove("_meta");
+        }
+        object.insert(field.to_string(), Value::String(BASE64.encode(data)));
+        restored += 1;
+    }
+
+    Ok(restored)
+}
+
+/// Objects of a JSON-RPC result that carry a base64 payload, with the payload's field
+fn payloads(message: &mut Value) -> Vec<(&mut Map<String, Value>, &'static str)> {
+    let mut payloads = Vec::new();
+    let Some(result) = message.get_mut("result").and_then(Value::as_object_mut) else {
+        return payloads;
+    };
+
+    for (key, value) in result.iter_mut() {
+        let Some(items) = value.as_array_mut() else {
+            continue;
+        };
+
+        for item in items.iter_mut().filter_map(Value::as_object_mut) {
+            match key.as_str() {
+                // `tools/call` and `prompts/get` content
+                "content" => {
+                    let kind = item.get("type").and_then(Value::as_str).unwrap_or_default();
+                    if kind == "image" || kind == "audio" {
+                        payloads.push((item, "data"));
+                    } else if kind == "resource" {
+                        if let Some(resource) = item.get_mut("resource").and_then(Value::as_object_mut) {
+                            payloads.push((resource, "blob"));
+                        }
+                    }
+                }
+                // `resources/read` contents
+                "contents" => payloads.push((item, "blob")),
+                _ => {}
+            }
+        }
+    }
+
+    payloads
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_offload_and_restore() {
+        let dir = std::env::temp_dir().join(format!("cvm-blobs-{}", std::process::id()));
+        let store = FileBlobStore::new(&dir);
+
+        let image = BASE64.encode(vec![7u8; 4096]);
+        let original = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "result": {
+                "content": [
+                    { "type": "text", "text": "a cat" },
+                    { "type": "image", "data": image, "mimeType": "image/png" },
+                    { "type": "image", "data": "AAAA", "mimeType": "image/png" },
+                ],
+            },
+        });
+
+        let mut message = original.clone();
+        assert_eq!(offload(&store, &mut message, 1024).await.unwrap(), 1);
+        let reference = &message["result"]["content"][1];
+        assert_eq!(reference["data"], "");
+        assert_eq!(reference["_meta"][BLOB_META_KEY]["size"], 4096);
+        assert_eq!(message["result"]["content"][2]["data"], "AAAA");
+
+        let mut restored = message.clone();
+        assert_eq!(restore(&store, &mut restored).await.unwrap(), 1);
+        assert_eq!(restored, original);
+
+        // A tampered blob is rejected
+        let sha256 = reference["_meta"][BLOB_META_KEY]["sha256"].as_str().unwrap();
+        std::fs::write(dir.join(sha256), b"not the image").unwrap();
+        assert!(restore(&store, &mut message).await.is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_advertise() {
+        let mut request = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "initialize",
+            "params": { "capabilities": { "roots": {} } },
+        });
+        assert!(!accepted(&request["params"]["capabilities"]));
+
+        advertise(&mut request);
+        assert!(accepted(&request["params"]["capabilities"]));
+        assert_eq!(request["params"]["capabilities"]["roots"], json!({}));
+    }
+}
@@ -0,0 +1,65 @@
+//! Bounded caches used for de-duplication
+
+use std::collections::{HashSet, VecDeque};
+use std::hash::Hash;
+
+/// Set that remembers at most `capacity` entries, evicting the oldest first
+#[derive(Debug)]
+pub(crate) struct BoundedSet<T> {
+    capacity: usize,
+    entries: HashSet<T>,
+    order: VecDeque<T>,
+}
+
+impl<T> BoundedSet<T>
+where
+    T: Eq + Hash + Clone,
+{
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            entries: HashSet::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    /// Insert a value, returning `false` if it was already present
+    pub(crate) fn insert(&mut self, value: T) -> bool {
+        if !self.entries.insert(value.clone()) {
+            return false;
+        }
+
+        self.order.push_back(value);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        true
+    }
+
+    #[cfg(test)]
+    pub(crate) fn contains(&self, value: &T) -> bool {
+        self.entries.contains(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bounded_set_evicts_oldest() {
+        let mut set = BoundedSet::new(2);
+
+        assert!(set.insert(1));
+        assert!(!set.insert(1));
+        assert!(set.insert(2));
+        assert!(set.insert(3));
+
+        assert!(!set.contains(&1));
+        assert!(set.contains(&2));
+        assert!(set.contains(&3));
+    }
+}
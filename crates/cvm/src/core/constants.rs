@@ -21,6 +21,9 @@ pub const RESOURCETEMPLATES_LIST_KIND: u16 = 11319;
 /// Prompts list (addressable)
 pub const PROMPTS_LIST_KIND: u16 = 11320;
 
+/// MCP protocol version advertised in announcements and `initialize` results
+pub const MCP_PROTOCOL_VERSION: &str = "2025-06-18";
+
 /// Nostr tag constants
 pub mod tags {
     /// Public key tag
@@ -46,11 +49,47 @@ pub mod tags {
 
     /// Support encryption tag
     pub const SUPPORT_ENCRYPTION: &str = "support_encryption";
+
+    /// Minimum NIP-13 difficulty a server requires of inbound events
+    pub const MIN_POW: &str = "min_pow";
+
+    /// Seconds between a server's re-announcements
+    pub const ANNOUNCE_INTERVAL: &str = "announce_interval";
+
+    /// Chunk tag for messages split across events: transfer id, index, total, sha256
+    pub const CHUNK: &str = "chunk";
 }
 
 /// Maximum message size (1MB)
 pub const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
 
+/// Default size above which messages are split into chunk events (32KB)
+pub const DEFAULT_CHUNK_SIZE: usize = 32 * 1024;
+
+/// Default size above which base64 payloads are offloaded to a blob store (16KB)
+pub const DEFAULT_BLOB_THRESHOLD: usize = 16 * 1024;
+
+/// Default size limit of blobs a client downloads (16MB)
+pub const DEFAULT_MAX_BLOB_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Default highest NIP-13 difficulty a client mines; each bit doubles the work, and
+/// mining cannot be interrupted once started
+pub const DEFAULT_MAX_POW: u8 = 28;
+
+/// Default number of event IDs a server remembers for de-duplication
+pub const DEFAULT_SEEN_CACHE_SIZE: usize = 10_000;
+
+/// Default time a queued message may wait for a relay (5 minutes, the default replay
+/// window of servers)
+pub const DEFAULT_OUTBOX_MAX_AGE_SECS: u64 = 300;
+
+/// Interval between re-announcements of long-running servers (10 minutes)
+pub const DEFAULT_ANNOUNCE_INTERVAL_SECS: u64 = 600;
+
+/// Re-announcements a server may miss before it counts as offline and its
+/// announcements expire
+pub const MISSED_ANNOUNCEMENTS_OFFLINE: u64 = 2;
+
 /// NIP-44 salt for HKDF
 pub const NIP44_SALT: &str = "nip44-v2";
 
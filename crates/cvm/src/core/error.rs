@@ -22,6 +22,15 @@ pub enum Error {
     #[error("Transport error: {0}")]
     Transport(String),
 
+    #[error("Relay authentication failed: {0}")]
+    Auth(String),
+
+    #[error("Key error: {0}")]
+    Key(String),
+
+    #[error("Blob error: {0}")]
+    Blob(String),
+
     #[error("Serialization error: {0}")]
     Serialization(#[from] serde_json::Error),
 
@@ -40,6 +49,9 @@ pub enum Error {
     #[error("Timeout")]
     Timeout,
 
+    #[error("Request cancelled")]
+    Cancelled,
+
     #[error("I/O error: {0}")]
     Io(#[from] std::io::Error),
 
@@ -1,5 +1,6 @@
 //! Core types and constants for ContextVM
 
+pub(crate) mod cache;
 pub mod constants;
 pub mod error;
 pub mod types;
@@ -1,11 +1,15 @@
 //! Core types for ContextVM protocol
 
+use super::constants::{tags, MISSED_ANNOUNCEMENTS_OFFLINE, SERVER_ANNOUNCEMENT_KIND};
+use nostr_sdk::prelude::{Event, EventId, Kind, PublicKey, Timestamp};
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
 /// Encryption mode for transport
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub enum EncryptionMode {
     /// Encrypt messages if incoming message was encrypted
+    #[default]
     Optional,
     /// Enforce encryption for all messages
     Required,
@@ -13,10 +17,37 @@ pub enum EncryptionMode {
     Disabled,
 }
 
-impl Default for EncryptionMode {
-    fn default() -> Self {
-        Self::Optional
+impl EncryptionMode {
+    /// Whether a message with the given encryption state is acceptable under this mode
+    pub fn accepts(&self, is_encrypted: bool) -> bool {
+        match self {
+            Self::Optional => true,
+            Self::Required => is_encrypted,
+            Self::Disabled => !is_encrypted,
+        }
     }
+
+    /// Whether to encrypt a message to a peer whose last message had the given encryption state
+    pub fn encrypt_for(&self, peer_encrypted: bool) -> bool {
+        match self {
+            Self::Optional => peer_encrypted,
+            Self::Required => true,
+            Self::Disabled => false,
+        }
+    }
+}
+
+/// What a client does with messages that no relay accepts
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DeliveryMode {
+    /// Fail the send
+    #[default]
+    FailFast,
+    /// Keep the message in the outbox and retry it once a relay is reachable
+    ///
+    /// Only notifications survive a restart; queued requests are dropped with the
+    /// process that waits for their responses.
+    Queued,
 }
 
 /// Server information for announcements
@@ -29,6 +60,100 @@ pub struct ServerInfo {
     pub about: Option<String>,
 }
 
+/// Server announcement (kind 11316) as published by a ContextVM server
+#[derive(Debug, Clone)]
+pub struct ServerAnnouncement {
+    pub pubkey: PublicKey,
+    pub server_info: ServerInfo,
+    pub supports_encryption: bool,
+    /// Minimum NIP-13 difficulty of events sent to the server
+    pub min_pow: Option<u8>,
+    /// MCP `initialize` result carried in the event content
+    pub initialize_result: serde_json::Value,
+    /// When the announcement was published
+    pub last_seen: Timestamp,
+    /// How often the server re-announces itself while running, if it does
+    pub announce_interval: Option<Duration>,
+}
+
+impl ServerAnnouncement {
+    /// Parse an announcement event, preferring tags and falling back to `serverInfo`
+    pub fn from_event(event: &Event) -> Option<Self> {
+        if event.kind != Kind::from(SERVER_ANNOUNCEMENT_KIND) {
+            return None;
+        }
+
+        let initialize_result: serde_json::Value = serde_json::from_str(&event.content).ok()?;
+        let tag_value = |name: &str| {
+            event.tags.iter().find_map(|tag| match tag.as_slice() {
+                [key, value, ..] if key == name => Some(value.clone()),
+                _ => None,
+            })
+        };
+        let content_value = |key: &str| {
+            initialize_result["serverInfo"][key]
+                .as_str()
+                .or_else(|| initialize_result[key].as_str())
+                .map(String::from)
+        };
+
+        let server_info = ServerInfo {
+            name: tag_value(tags::NAME).or_else(|| content_value("name")),
+            version: content_value("version"),
+            picture: tag_value(tags::PICTURE),
+            website: tag_value(tags::WEBSITE),
+            about: tag_value(tags::ABOUT).or_else(|| {
+                initialize_result["instructions"].as_str().map(String::from)
+            }),
+        };
+
+        let supports_encryption = event
+            .tags
+            .iter()
+            .any(|tag| tag.as_slice().first().map(String::as_str) == Some(tags::SUPPORT_ENCRYPTION));
+
+        let min_pow = tag_value(tags::MIN_POW).and_then(|difficulty| difficulty.parse().ok());
+
+        let announce_interval = tag_value(tags::ANNOUNCE_INTERVAL)
+            .and_then(|secs| secs.parse().ok())
+            .map(Duration::from_secs);
+
+        Some(Self {
+            pubkey: event.pubkey,
+            server_info,
+            supports_encryption,
+            min_pow,
+            initialize_result,
+            last_seen: event.created_at,
+            announce_interval,
+        })
+    }
+
+    /// Whether the server re-announced itself recently enough to be running
+    ///
+    /// `None` if the server does not re-announce itself, so its status is unknown.
+    pub fn is_online(&self) -> Option<bool> {
+        let interval = self.announce_interval?;
+        let age = Timestamp::now().as_u64().saturating_sub(self.last_seen.as_u64());
+        Some(age <= interval.as_secs() * MISSED_ANNOUNCEMENTS_OFFLINE)
+    }
+}
+
+/// Token-bucket limit on the messages a single client may send
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RateLimit {
+    /// Messages a client may send in a burst
+    pub burst: u32,
+    /// Sustained messages per second
+    pub per_second: f64,
+}
+
+impl RateLimit {
+    pub fn new(burst: u32, per_second: f64) -> Self {
+        Self { burst, per_second }
+    }
+}
+
 /// Client session state
 #[derive(Debug, Clone)]
 pub struct ClientSession {
@@ -36,6 +161,12 @@ pub struct ClientSession {
     pub is_initialized: bool,
     pub is_encrypted: bool,
     pub last_activity: std::time::Instant,
+    /// Protocol version requested by the client, replaced by the server's answer
+    pub protocol_version: Option<String>,
+    pub client_capabilities: Option<serde_json::Value>,
+    pub client_info: Option<serde_json::Value>,
+    /// Request event of the client's `initialize` call
+    pub initialize_event_id: Option<EventId>,
 }
 
 impl ClientSession {
@@ -45,6 +176,10 @@ impl ClientSession {
             is_initialized: false,
             is_encrypted,
             last_activity: std::time::Instant::now(),
+            protocol_version: None,
+            client_capabilities: None,
+            client_info: None,
+            initialize_event_id: None,
         }
     }
 
@@ -55,4 +190,63 @@ impl ClientSession {
     pub fn mark_initialized(&mut self) {
         self.is_initialized = true;
     }
+
+    /// Whether the client advertised that it downloads offloaded blobs
+    pub fn accepts_blobs(&self) -> bool {
+        self.client_capabilities
+            .as_ref()
+            .is_some_and(crate::blob::accepted)
+    }
+
+    /// Record the parameters of an `initialize` request, restarting the handshake
+    pub fn record_initialize(&mut self, params: &serde_json::Value, event_id: EventId) {
+        self.is_initialized = false;
+        self.protocol_version = params["protocolVersion"].as_str().map(String::from);
+        self.client_capabilities = params.get("capabilities").cloned();
+        self.client_info = params.get("clientInfo").cloned();
+        self.initialize_event_id = Some(event_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encryption_mode_policy() {
+        assert!(EncryptionMode::Optional.accepts(true));
+        assert!(EncryptionMode::Optional.accepts(false));
+        assert!(EncryptionMode::Required.accepts(true));
+        assert!(!EncryptionMode::Required.accepts(false));
+        assert!(!EncryptionMode::Disabled.accepts(true));
+        assert!(EncryptionMode::Disabled.accepts(false));
+
+        assert!(EncryptionMode::Optional.encrypt_for(true));
+        assert!(!EncryptionMode::Optional.encrypt_for(false));
+        assert!(EncryptionMode::Required.encrypt_for(false));
+        assert!(!EncryptionMode::Disabled.encrypt_for(true));
+    }
+
+    #[test]
+    fn test_announcement_online_status() {
+        use nostr_sdk::prelude::{EventBuilder, Keys, Tag, TagKind};
+
+        let keys = Keys::generate();
+        let announce = |created_at: u64, tags: Vec<Tag>| {
+            let event = EventBuilder::new(Kind::from(SERVER_ANNOUNCEMENT_KIND), "{}")
+                .tags(tags)
+                .custom_created_at(Timestamp::from(created_at))
+                .sign_with_keys(&keys)
+                .unwrap();
+            ServerAnnouncement::from_event(&event).unwrap()
+        };
+        let interval = Tag::custom(TagKind::custom(tags::ANNOUNCE_INTERVAL), ["60"]);
+        let now = Timestamp::now().as_u64();
+
+        let fresh = announce(now - 30, vec![interval.clone()]);
+        assert_eq!(fresh.announce_interval, Some(Duration::from_secs(60)));
+        assert_eq!(fresh.is_online(), Some(true));
+        assert_eq!(announce(now - 600, vec![interval]).is_online(), Some(false));
+        assert_eq!(announce(now - 600, Vec::new()).is_online(), None);
+    }
 }
@@ -1,7 +1,8 @@
 //! Encryption and gift wrapping for ContextVM
 //!
 //! This module provides wrappers around nostr-sdk's NIP-44 and NIP-59 functionality.
-//! The actual gift wrapping is done via the Client, not directly here.
+//! Gift wraps are normally built by nostr-sdk; [`gift_wrap_with_pow`] builds them by
+//! hand when the outer event must carry NIP-13 proof of work.
 
 use crate::core::error::{Error, Result};
 use nostr_sdk::prelude::*;
@@ -36,6 +37,45 @@ where
         .map_err(|e| Error::Decryption(e.to_string()))
 }
 
+/// Gift wrap a rumor whose outer event meets a NIP-13 difficulty
+///
+/// Follows NIP-59 like nostr-sdk's gift wrap: the rumor is sealed by `signer` and the
+/// seal encrypted with a one-time key, which also signs the mined gift wrap. Mining
+/// runs on the blocking thread pool.
+pub async fn gift_wrap_with_pow<T, I>(
+    signer: &T,
+    receiver_pubkey: &PublicKey,
+    rumor: UnsignedEvent,
+    extra_tags: I,
+    difficulty: u8,
+) -> Result<Event>
+where
+    T: NostrSigner,
+    I: IntoIterator<Item = Tag>,
+{
+    let seal = EventBuilder::seal(signer, receiver_pubkey, rumor)
+        .await
+        .map_err(|e| Error::Encryption(e.to_string()))?
+        .sign(signer)
+        .await
+        .map_err(|e| Error::Encryption(e.to_string()))?;
+
+    let keys = Keys::generate();
+    let content = nip44::encrypt(keys.secret_key(), receiver_pubkey, seal.as_json(), nip44::Version::default())
+        .map_err(|e| Error::Encryption(e.to_string()))?;
+
+    let builder = EventBuilder::new(Kind::GiftWrap, content)
+        .tag(Tag::public_key(*receiver_pubkey))
+        .tags(extra_tags)
+        .custom_created_at(Timestamp::tweaked(nip59::RANGE_RANDOM_TIMESTAMP_TWEAK))
+        .pow(difficulty);
+
+    tokio::task::spawn_blocking(move || builder.sign_with_keys(&keys))
+        .await
+        .map_err(|e| Error::Other(e.to_string()))?
+        .map_err(|e| Error::Encryption(e.to_string()))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -57,4 +97,20 @@ mod tests {
 
         assert_eq!(plaintext, decrypted);
     }
+
+    #[tokio::test]
+    async fn test_gift_wrap_with_pow() {
+        let sender = Keys::generate();
+        let receiver = Keys::generate();
+        let rumor = EventBuilder::text_note("mined").build(sender.public_key());
+
+        let gift_wrap = gift_wrap_with_pow(&sender, &receiver.public_key(), rumor, [], 8)
+            .await
+            .unwrap();
+        assert!(gift_wrap.id.check_pow(8));
+
+        let unwrapped = UnwrappedGift::from_gift_wrap(&receiver, &gift_wrap).await.unwrap();
+        assert_eq!(unwrapped.sender, sender.public_key());
+        assert_eq!(unwrapped.rumor.content, "mined");
+    }
 }
@@ -10,16 +10,27 @@ pub mod transport;
 pub mod relay;
 pub mod signer;
 pub mod encryption;
+pub mod blob;
+pub mod metrics;
+
+#[cfg(feature = "test-relay")]
+pub mod testing;
 
 // Re-export commonly used types
 pub use core::{
     constants, error, types,
     error::{Error, Result},
-    types::{EncryptionMode, ServerInfo, ClientSession},
+    types::{EncryptionMode, DeliveryMode, ServerInfo, ServerAnnouncement, ClientSession, RateLimit},
 };
 
-pub use transport::client::{NostrClientTransport, NostrClientTransportConfig};
+pub use transport::client::{
+    CancelHandle, NostrClientTransport, NostrClientTransportConfig, RequestHandle, RequestOptions,
+    ServerNotification,
+};
 pub use transport::server::{NostrServerTransport, NostrServerTransportConfig, IncomingMessage};
 
-pub use relay::RelayPool;
-pub use signer::{Keys, NostrSigner, PublicKey, from_sk, generate};
+pub use relay::{AuthStatus, RelayHealth, RelayPool, Subscription};
+pub use blob::{BlobDescriptor, BlobStore, FileBlobStore};
+#[cfg(feature = "blossom")]
+pub use blob::BlossomClient;
+pub use signer::{Keys, Keystore, NostrSigner, PublicKey, from_sk, from_sk_with_passphrase, generate};
@@ -0,0 +1,129 @@
+//! Prometheus exporter on a local port
+//!
+//! A minimal HTTP/1.1 responder: `GET /metrics` returns [`super::Metrics::render`] of
+//! the global metrics, anything else a 404. It only binds loopback addresses, since the
+//! metrics reveal who talks to whom and how often.
+
+use crate::core::error::{Error, Result};
+use std::net::SocketAddr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+/// Largest request head read before answering
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+
+/// Running exporter; it stops when dropped
+#[derive(Debug)]
+pub struct Exporter {
+    addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl Exporter {
+    /// Address the exporter listens on
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for Exporter {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Serve the global metrics at `http://<addr>/metrics`
+///
+/// `addr` must be a loopback address; port 0 picks a free port, see
+/// [`Exporter::local_addr`].
+pub async fn serve(addr: SocketAddr) -> Result<Exporter> {
+    if !addr.ip().is_loopback() {
+        return Err(Error::Other(format!(
+            "Refusing to expose metrics on {}: use a loopback address",
+            addr
+        )));
+    }
+
+    let listener = TcpListener::bind(addr).await?;
+    let addr = listener.local_addr()?;
+    tracing::info!("Serving metrics on http://{}/metrics", addr);
+
+    let task = tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(async move {
+                        if let Err(e) = respond(stream).await {
+                            tracing::debug!("Metrics request failed: {}", e);
+                        }
+                    });
+                }
+                Err(e) => tracing::warn!("Failed to accept metrics connection: {}", e),
+            }
+        }
+    });
+
+    Ok(Exporter { addr, task })
+}
+
+async fn respond(mut stream: TcpStream) -> Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") && request.len() < MAX_REQUEST_SIZE {
+        let read = stream.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..read]);
+    }
+
+    let request_line = request.split(|b| *b == b'\n').next().unwrap_or_default();
+    let mut parts = std::str::from_utf8(request_line).unwrap_or_default().split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => (
+            "200 OK",
+            "text/plain; version=0.0.4; charset=utf-8",
+            super::global().render(),
+        ),
+        _ => ("404 Not Found", "text/plain; charset=utf-8", "Not found\n".to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn get(addr: SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_serve_metrics() {
+        assert!(serve("0.0.0.0:0".parse().unwrap()).await.is_err());
+
+        let exporter = serve("127.0.0.1:0".parse().unwrap()).await.unwrap();
+        let response = get(exporter.local_addr(), "/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("# TYPE cvm_requests_sent_total counter"));
+
+        let response = get(exporter.local_addr(), "/").await;
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
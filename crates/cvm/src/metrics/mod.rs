@@ -0,0 +1,255 @@
+//! Transport metrics
+//!
+//! Counters and latency histograms shared by every client and server transport of the
+//! process, read with [`global`]. [`Metrics::render`] formats them in the Prometheus
+//! text exposition format; [`serve`] exposes that on a local HTTP port.
+//!
+//! Per-request detail goes to `tracing` spans instead: `cvm.request` on clients and
+//! `cvm.message` on servers, carrying the event ID, peer, encryption and latency.
+
+mod exporter;
+
+pub use exporter::{serve, Exporter};
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Upper bounds of the latency histogram buckets, in seconds
+const LATENCY_BUCKETS: [f64; 10] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+/// Metrics of all transports in this process
+pub fn global() -> &'static Metrics {
+    METRICS.get_or_init(Metrics::default)
+}
+
+/// Why an inbound event never reached the application
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropReason {
+    /// Already received from another relay
+    Duplicate,
+    /// Below the required NIP-13 proof of work
+    InsufficientPow,
+    /// Past its NIP-40 expiration
+    Expired,
+    /// Created outside the replay window
+    Replay,
+    /// Signature or seal does not match the claimed author
+    Forged,
+    /// Encrypted or plaintext against the encryption mode
+    EncryptionMode,
+    /// Client denied, rate limited or over the session limit
+    Refused,
+    /// Malformed message or chunk
+    Invalid,
+    /// Response to no pending request, usually one that timed out
+    Unmatched,
+}
+
+impl DropReason {
+    const ALL: [DropReason; 9] = [
+        DropReason::Duplicate,
+        DropReason::InsufficientPow,
+        DropReason::Expired,
+        DropReason::Replay,
+        DropReason::Forged,
+        DropReason::EncryptionMode,
+        DropReason::Refused,
+        DropReason::Invalid,
+        DropReason::Unmatched,
+    ];
+
+    /// Value of the `reason` label
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DropReason::Duplicate => "duplicate",
+            DropReason::InsufficientPow => "insufficient_pow",
+            DropReason::Expired => "expired",
+            DropReason::Replay => "replay",
+            DropReason::Forged => "forged",
+            DropReason::EncryptionMode => "encryption_mode",
+            DropReason::Refused => "refused",
+            DropReason::Invalid => "invalid",
+            DropReason::Unmatched => "unmatched",
+        }
+    }
+}
+
+/// Monotonic counter
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Latency histogram with fixed buckets
+#[derive(Debug)]
+pub struct Histogram {
+    /// Observations per bucket, not cumulative; the last one is `+Inf`
+    buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    sum_micros: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            sum_micros: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Histogram {
+    pub fn count(&self) -> u64 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Sum of all observations
+    pub fn sum(&self) -> Duration {
+        Duration::from_micros(self.sum_micros.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn observe(&self, latency: Duration) {
+        let seconds = latency.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Counters and latencies of the ContextVM transports
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Requests sent by clients
+    pub requests_sent: Counter,
+    /// Responses matched to a pending client request
+    pub responses_received: Counter,
+    /// Client requests that got no response in time
+    pub request_timeouts: Counter,
+    /// Requests handed to server applications
+    pub requests_received: Counter,
+    /// Responses sent by servers
+    pub responses_sent: Counter,
+    /// Notifications sent, by clients and servers
+    pub notifications_sent: Counter,
+    /// Notifications received, by clients and servers
+    pub notifications_received: Counter,
+    /// Gift wraps that could not be unwrapped
+    pub decrypt_failures: Counter,
+    /// Events no relay accepted
+    pub publish_failures: Counter,
+    dropped: [Counter; DropReason::ALL.len()],
+    /// Time from sending a request to receiving its response
+    pub client_latency: Histogram,
+    /// Time from receiving a request to sending its response
+    pub server_latency: Histogram,
+}
+
+impl Metrics {
+    /// Inbound events dropped for `reason`
+    pub fn dropped(&self, reason: DropReason) -> u64 {
+        self.dropped[reason as usize].get()
+    }
+
+    pub(crate) fn record_drop(&self, reason: DropReason) {
+        self.dropped[reason as usize].inc();
+    }
+
+    /// All metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        let counters = [
+            ("cvm_requests_sent_total", "Requests sent by clients", &self.requests_sent),
+            ("cvm_responses_received_total", "Responses received by clients", &self.responses_received),
+            ("cvm_request_timeouts_total", "Client requests that timed out", &self.request_timeouts),
+            ("cvm_requests_received_total", "Requests received by servers", &self.requests_received),
+            ("cvm_responses_sent_total", "Responses sent by servers", &self.responses_sent),
+            ("cvm_notifications_sent_total", "Notifications sent", &self.notifications_sent),
+            ("cvm_notifications_received_total", "Notifications received", &self.notifications_received),
+            ("cvm_decrypt_failures_total", "Gift wraps that could not be unwrapped", &self.decrypt_failures),
+            ("cvm_publish_failures_total", "Events no relay accepted", &self.publish_failures),
+        ];
+        for (name, help, counter) in counters {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            let _ = writeln!(out, "{} {}", name, counter.get());
+        }
+
+        let _ = writeln!(out, "# HELP cvm_dropped_events_total Inbound events dropped before reaching the application");
+        let _ = writeln!(out, "# TYPE cvm_dropped_events_total counter");
+        for reason in DropReason::ALL {
+            let _ = writeln!(out, "cvm_dropped_events_total{{reason=\"{}\"}} {}", reason.as_str(), self.dropped(reason));
+        }
+
+        let _ = writeln!(out, "# HELP cvm_request_duration_seconds Time from request to response");
+        let _ = writeln!(out, "# TYPE cvm_request_duration_seconds histogram");
+        for (role, histogram) in [("client", &self.client_latency), ("server", &self.server_latency)] {
+            let mut cumulative = 0;
+            for (i, bucket) in histogram.buckets.iter().enumerate() {
+                cumulative += bucket.load(Ordering::Relaxed);
+                let bound = LATENCY_BUCKETS.get(i).map_or("+Inf".to_string(), |bound| bound.to_string());
+                let _ = writeln!(
+                    out,
+                    "cvm_request_duration_seconds_bucket{{role=\"{}\",le=\"{}\"}} {}",
+                    role, bound, cumulative
+                );
+            }
+            let _ = writeln!(
+                out,
+                "cvm_request_duration_seconds_sum{{role=\"{}\"}} {}",
+                role,
+                histogram.sum().as_secs_f64()
+            );
+            let _ = writeln!(out, "cvm_request_duration_seconds_count{{role=\"{}\"}} {}", role, histogram.count());
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = Metrics::default();
+        metrics.requests_sent.inc();
+        metrics.requests_sent.inc();
+        metrics.record_drop(DropReason::Replay);
+        metrics.client_latency.observe(Duration::from_millis(500));
+        metrics.client_latency.observe(Duration::from_secs(3));
+        metrics.client_latency.observe(Duration::from_secs(120));
+
+        let text = metrics.render();
+        assert!(text.contains("# TYPE cvm_requests_sent_total counter\ncvm_requests_sent_total 2\n"));
+        assert!(text.contains("cvm_dropped_events_total{reason=\"replay\"} 1\n"));
+        assert!(text.contains("cvm_dropped_events_total{reason=\"duplicate\"} 0\n"));
+
+        // Buckets are cumulative
+        assert!(text.contains("cvm_request_duration_seconds_bucket{role=\"client\",le=\"0.25\"} 0\n"));
+        assert!(text.contains("cvm_request_duration_seconds_bucket{role=\"client\",le=\"0.5\"} 1\n"));
+        assert!(text.contains("cvm_request_duration_seconds_bucket{role=\"client\",le=\"5\"} 2\n"));
+        assert!(text.contains("cvm_request_duration_seconds_bucket{role=\"client\",le=\"+Inf\"} 3\n"));
+        assert!(text.contains("cvm_request_duration_seconds_sum{role=\"client\"} 123.5\n"));
+        assert!(text.contains("cvm_request_duration_seconds_count{role=\"server\"} 0\n"));
+    }
+}
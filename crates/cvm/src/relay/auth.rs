@@ -0,0 +1,134 @@
+//! NIP-42 relay authentication
+//!
+//! Relays may challenge clients to authenticate before accepting events or
+//! subscriptions. The pool answers challenges with its signer, records the outcome in
+//! the relay's [`RelayHealth`](super::RelayHealth) and restores the subscriptions a relay
+//! may have closed while we were unauthenticated.
+
+use super::health::{self, HealthMap, SubscriptionMap};
+use crate::core::error::{Error, Result};
+use nostr_sdk::prelude::*;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// How long to wait for a relay to authenticate us
+pub(crate) const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often [`wait_for_auth`] checks the authentication state
+const AUTH_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Prefix of relay messages refusing an event or subscription until we authenticate
+const AUTH_REQUIRED_PREFIX: &str = "auth-required:";
+
+/// NIP-42 authentication state of a relay
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum AuthStatus {
+    /// The relay has not challenged us on the current connection
+    #[default]
+    None,
+    /// The relay accepted our AUTH event
+    Authenticated,
+    /// The relay rejected our AUTH event
+    Failed(String),
+}
+
+/// Whether a relay refused an event or subscription because we are not authenticated
+pub(crate) fn is_auth_required(message: &str) -> bool {
+    message.starts_with(AUTH_REQUIRED_PREFIX)
+}
+
+/// Answer an AUTH challenge of a relay
+pub(crate) async fn authenticate(
+    client: Arc<Client>,
+    url: RelayUrl,
+    challenge: String,
+    health: HealthMap,
+    subscriptions: SubscriptionMap,
+) {
+    let result = async {
+        let event = client
+            .sign_event_builder(EventBuilder::auth(challenge, url.clone()))
+            .await
+            .map_err(|e| e.to_string())?;
+        let relay = client.relay(&url).await.map_err(|e| e.to_string())?;
+        send_auth(&relay, event).await
+    }
+    .await;
+
+    let status = match &result {
+        Ok(()) => AuthStatus::Authenticated,
+        Err(reason) => AuthStatus::Failed(reason.clone()),
+    };
+    health::update(&health, &url.to_string(), |entry| entry.auth = status).await;
+
+    match result {
+        Ok(()) => {
+            tracing::info!("Authenticated to relay {}", url);
+            health::resubscribe(&client, &url, &subscriptions).await;
+        }
+        Err(reason) => tracing::warn!("Authentication to relay {} failed: {}", url, reason),
+    }
+}
+
+/// Send an AUTH event and wait for the relay's `OK`
+async fn send_auth(relay: &Relay, event: Event) -> std::result::Result<(), String> {
+    // Listen before sending so the reply cannot be missed
+    let mut notifications = relay.notifications();
+    let event_id = event.id;
+    relay.send_msg(ClientMessage::auth(event)).map_err(|e| e.to_string())?;
+
+    let reply = tokio::time::timeout(AUTH_TIMEOUT, async {
+        while let Ok(notification) = notifications.recv().await {
+            if let RelayNotification::Message {
+                message: RelayMessage::Ok { event_id: id, status, message },
+            } = notification
+            {
+                if id == event_id {
+                    return Some((status, message.into_owned()));
+                }
+            }
+        }
+        None
+    })
+    .await;
+
+    match reply {
+        Ok(Some((true, _))) => Ok(()),
+        Ok(Some((false, message))) => Err(message),
+        Ok(None) => Err("connection closed".to_string()),
+        Err(_) => Err("no reply to AUTH".to_string()),
+    }
+}
+
+/// Wait until a relay has authenticated us, up to `deadline`
+///
+/// Returns `false` if the relay has not challenged us by then, and an error if it
+/// rejected our authentication.
+pub(crate) async fn wait_for_auth(health: &HealthMap, url: &str, deadline: Instant) -> Result<bool> {
+    loop {
+        let status = health.read().await.get(url).map(|entry| entry.auth.clone());
+        match status {
+            Some(AuthStatus::Authenticated) => return Ok(true),
+            Some(AuthStatus::Failed(reason)) => return Err(Error::Auth(format!("{}: {}", url, reason))),
+            _ => {}
+        }
+
+        if Instant::now() >= deadline {
+            return Ok(false);
+        }
+        tokio::time::sleep(AUTH_POLL_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auth_required_messages() {
+        assert!(is_auth_required("auth-required: we only accept events from members"));
+        assert!(!is_auth_required("restricted: not a member"));
+        assert!(!is_auth_required("blocked: auth-required: nested"));
+    }
+}
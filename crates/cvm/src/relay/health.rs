@@ -0,0 +1,161 @@
+//! Relay health monitoring
+//!
+//! nostr-sdk reconnects dropped relays and restores their subscriptions on its own; a
+//! background task mirrors each relay's status and latency into [`RelayHealth`] and
+//! answers NIP-42 challenges as they arrive.
+
+use super::auth::{self, AuthStatus};
+use nostr_sdk::prelude::*;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, RwLock};
+
+/// How often the monitor reads relay status
+const MONITOR_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Health of one relay as seen by a [`RelayPool`](super::RelayPool)
+#[derive(Debug, Clone)]
+pub struct RelayHealth {
+    pub url: String,
+    pub connected: bool,
+    /// Average round trip measured by the SDK
+    pub latency: Option<Duration>,
+    /// Last authentication or publish error
+    pub last_error: Option<String>,
+    /// Connection attempts since the relay was last connected
+    pub reconnect_attempts: u32,
+    /// The relay was marked as requiring NIP-42 authentication
+    pub auth_required: bool,
+    /// NIP-42 authentication on the current connection
+    pub auth: AuthStatus,
+}
+
+impl RelayHealth {
+    fn new(url: String) -> Self {
+        Self {
+            url,
+            connected: false,
+            latency: None,
+            last_error: None,
+            reconnect_attempts: 0,
+            auth_required: false,
+            auth: AuthStatus::None,
+        }
+    }
+}
+
+pub(crate) type HealthMap = Arc<RwLock<HashMap<String, RelayHealth>>>;
+
+/// Live subscriptions of the pool, re-sent to relays once they authenticated us
+pub(crate) type SubscriptionMap = Arc<std::sync::Mutex<HashMap<SubscriptionId, Filter>>>;
+
+/// Update the health entry of a relay, creating it if needed
+pub(crate) async fn update<F>(health: &HealthMap, url: &str, f: F)
+where
+    F: FnOnce(&mut RelayHealth),
+{
+    let mut health = health.write().await;
+    f(health
+        .entry(url.to_string())
+        .or_insert_with(|| RelayHealth::new(url.to_string())));
+}
+
+/// Record an error against a relay
+pub(crate) async fn record_error(health: &HealthMap, url: &str, error: String) {
+    update(health, url, |entry| entry.last_error = Some(error)).await;
+}
+
+/// Per-relay bookkeeping of the monitor
+#[derive(Default)]
+struct MonitorState {
+    was_connected: bool,
+    /// Connection attempts the SDK had made when the relay was last connected
+    attempts_when_connected: usize,
+}
+
+/// Watch relays until the task is aborted
+pub(crate) async fn monitor_relays(client: Arc<Client>, health: HealthMap, subscriptions: SubscriptionMap) {
+    let mut states: HashMap<RelayUrl, MonitorState> = HashMap::new();
+    let mut interval = tokio::time::interval(MONITOR_INTERVAL);
+    let mut notifications = client.notifications();
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            notification = notifications.recv() => {
+                match notification {
+                    Ok(RelayPoolNotification::Message {
+                        relay_url,
+                        message: RelayMessage::Auth { challenge },
+                    }) => {
+                        tokio::spawn(auth::authenticate(
+                            client.clone(),
+                            relay_url,
+                            challenge.to_string(),
+                            health.clone(),
+                            subscriptions.clone(),
+                        ));
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                    _ => {}
+                }
+                continue;
+            }
+        }
+
+        for (url, relay) in client.relays().await {
+            let key = url.to_string();
+            let state = states.entry(url.clone()).or_default();
+            let connected = relay.status() == RelayStatus::Connected;
+            let stats = relay.stats();
+
+            if connected && !state.was_connected {
+                tracing::info!("Relay {} connected", key);
+            } else if !connected && state.was_connected {
+                tracing::warn!("Relay {} disconnected", key);
+            }
+            if connected {
+                state.attempts_when_connected = stats.attempts();
+            }
+            let lost = state.was_connected && !connected;
+            state.was_connected = connected;
+
+            let mut health = health.write().await;
+            let entry = health
+                .entry(key.clone())
+                .or_insert_with(|| RelayHealth::new(key));
+            entry.connected = connected;
+            entry.latency = stats.latency();
+            entry.reconnect_attempts = if connected {
+                0
+            } else {
+                stats.attempts().saturating_sub(state.attempts_when_connected) as u32
+            };
+            // Authentication is per connection
+            if lost {
+                entry.auth = AuthStatus::None;
+            }
+        }
+    }
+}
+
+/// Re-send the pool's subscriptions to a relay that authenticated us
+pub(crate) async fn resubscribe(client: &Client, url: &RelayUrl, subscriptions: &SubscriptionMap) {
+    let subscriptions: Vec<(SubscriptionId, Filter)> = match subscriptions.lock() {
+        Ok(subscriptions) => subscriptions
+            .iter()
+            .map(|(id, filter)| (id.clone(), filter.clone()))
+            .collect(),
+        Err(_) => return,
+    };
+
+    for (id, filter) in subscriptions {
+        if let Err(e) = client
+            .subscribe_with_id_to([url.clone()], id.clone(), filter, None)
+            .await
+        {
+            tracing::warn!("Failed to resubscribe {} on {}: {}", id, url, e);
+        }
+    }
+}
@@ -1,13 +1,43 @@
 //! Nostr relay pool management
 
+mod auth;
+mod health;
+
+pub use auth::AuthStatus;
+pub use health::RelayHealth;
+
+use crate::core::cache::BoundedSet;
 use crate::core::error::{Error, Result};
+use crate::encryption;
+use crate::metrics;
+use health::{HealthMap, SubscriptionMap};
 use nostr_sdk::prelude::*;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use std::time::Duration;
+use tokio::sync::{broadcast, mpsc, RwLock};
+use tokio::task::JoinHandle;
+
+/// Capacity of the event channel backing a [`Subscription`]
+const SUBSCRIPTION_CHANNEL_CAPACITY: usize = 100;
+
+/// Number of event IDs a [`Subscription`] remembers for de-duplication
+const SUBSCRIPTION_SEEN_CAPACITY: usize = 4096;
 
 /// Relay pool wrapper for managing Nostr relay connections
+///
+/// Dropped relays are reconnected, and their subscriptions restored, by nostr-sdk; see
+/// [`RelayPool::status`] for per-relay health.
+///
+/// NIP-42 AUTH challenges are answered with the pool's signer. Events a relay refuses
+/// with `auth-required` are re-sent once it has authenticated us; relays marked with
+/// [`RelayPool::require_auth`] must authenticate us before [`RelayPool::connect`] returns.
 pub struct RelayPool {
     client: Arc<Client>,
+    health: HealthMap,
+    /// Filters of live subscriptions, re-sent once a relay authenticated us
+    subscriptions: SubscriptionMap,
+    monitor: std::sync::Mutex<Option<JoinHandle<()>>>,
 }
 
 impl RelayPool {
@@ -16,31 +46,103 @@ impl RelayPool {
     where
         T: IntoNostrSigner,
     {
-        let client = Client::builder().signer(signer).build();
+        // Challenges are answered by the monitor, which tracks the outcome per relay
+        let options = ClientOptions::new().automatic_authentication(false);
+        let client = Client::builder().signer(signer).opts(options).build();
 
         Ok(Self {
             client: Arc::new(client),
+            health: Arc::new(RwLock::new(HashMap::new())),
+            subscriptions: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            monitor: std::sync::Mutex::new(None),
         })
     }
 
     /// Connect to relay URLs
+    ///
+    /// Idempotent: relays already in the pool are left alone, so this is cheap to call
+    /// before every operation.
     pub async fn connect(&self, relay_urls: &[String]) -> Result<()> {
+        let mut added = false;
         for url in relay_urls {
-            self.client.add_relay(url).await.map_err(|e| Error::Transport(e.to_string()))?;
+            added |= self.client.add_relay(url).await.map_err(|e| Error::Transport(e.to_string()))?;
+        }
+
+        // The monitor runs while the pool is connected; starting it marks a (re)connect
+        let started = match self.monitor.lock() {
+            Ok(mut monitor) if monitor.is_none() => {
+                *monitor = Some(tokio::spawn(health::monitor_relays(
+                    self.client.clone(),
+                    self.health.clone(),
+                    self.subscriptions.clone(),
+                )));
+                true
+            }
+            _ => false,
+        };
+
+        if added || started {
+            self.client.connect().await;
+            self.wait_for_required_auth().await?;
         }
 
-        self.client.connect().await;
+        Ok(())
+    }
+
+    /// Mark relays as requiring NIP-42 authentication
+    pub async fn require_auth(&self, relay_urls: &[String]) -> Result<()> {
+        for url in relay_urls {
+            let url = RelayUrl::parse(url).map_err(|e| Error::Transport(e.to_string()))?;
+            health::update(&self.health, &url.to_string(), |entry| entry.auth_required = true).await;
+        }
 
         Ok(())
     }
 
-    /// Disconnect from relays
+    /// Wait for relays marked as auth-required to authenticate us
+    ///
+    /// Relays that have not challenged us in time are only warned about: they may
+    /// challenge on demand, which [`RelayPool::publish`] handles.
+    async fn wait_for_required_auth(&self) -> Result<()> {
+        let required: Vec<String> = self
+            .health
+            .read()
+            .await
+            .values()
+            .filter(|entry| entry.auth_required && entry.auth != AuthStatus::Authenticated)
+            .map(|entry| entry.url.clone())
+            .collect();
+
+        let deadline = tokio::time::Instant::now() + auth::AUTH_TIMEOUT;
+        for url in required {
+            if !auth::wait_for_auth(&self.health, &url, deadline).await? {
+                tracing::warn!("Relay {} has not asked us to authenticate yet", url);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Disconnect from relays and stop reconnecting
     pub async fn disconnect(&self) -> Result<()> {
+        self.stop_monitor();
         self.client.disconnect().await;
         Ok(())
     }
 
+    /// Health of every relay in the pool
+    pub async fn status(&self) -> Vec<RelayHealth> {
+        let mut status: Vec<RelayHealth> = self.health.read().await.values().cloned().collect();
+        status.sort_by(|a, b| a.url.cmp(&b.url));
+        status
+    }
+
     /// Publish an event to relays
+    ///
+    /// Succeeds if at least one relay accepted the event; rejections are recorded in
+    /// the relays' [`RelayHealth`]. Relays that demand authentication get the event again
+    /// once they authenticated us; if none accepted it for lack of authentication, this
+    /// fails with [`Error::Auth`], and with [`Error::Transport`] for any other reason.
     pub async fn publish(&self, event: Event) -> Result<EventId> {
         let output = self
             .client
@@ -48,29 +150,273 @@ impl RelayPool {
             .await
             .map_err(|e| Error::Transport(e.to_string()))?;
 
-        Ok(output.val)
+        let mut accepted = !output.success.is_empty();
+        let mut auth_error = None;
+
+        for (url, error) in &output.failed {
+            let error = if auth::is_auth_required(error) {
+                match self.publish_after_auth(url, &event).await {
+                    Ok(()) => {
+                        accepted = true;
+                        continue;
+                    }
+                    Err(error) => {
+                        auth_error = Some(format!("{}: {}", url, error));
+                        error
+                    }
+                }
+            } else {
+                error.clone()
+            };
+            health::record_error(&self.health, &url.to_string(), error).await;
+        }
+
+        if !accepted {
+            metrics::global().publish_failures.inc();
+        }
+
+        match auth_error {
+            Some(error) if !accepted => Err(Error::Auth(error)),
+            _ if !accepted => Err(Error::Transport(format!(
+                "No relay accepted event {}",
+                output.val
+            ))),
+            _ => Ok(output.val),
+        }
     }
 
-    /// Subscribe to events matching filters
-    pub async fn subscribe(&self, filters: Vec<Filter>, timeout: Duration) -> Result<Events> {
-        // Combine multiple filters using OR logic
-        let combined_filter = filters.into_iter().reduce(|acc, _f| {
-            // We'll just use the first filter for simplicity
-            // In a real implementation, you'd need to properly combine filters
-            acc
-        }).unwrap_or_else(Filter::new);
+    /// Gift wrap a rumor for `receiver` and publish it
+    ///
+    /// A non-zero `difficulty` mines NIP-13 proof of work into the gift wrap.
+    pub async fn publish_gift_wrap<I>(
+        &self,
+        receiver: &PublicKey,
+        rumor: UnsignedEvent,
+        extra_tags: I,
+        difficulty: u8,
+    ) -> Result<EventId>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let event = self.gift_wrap(receiver, rumor, extra_tags, difficulty).await?;
+        self.publish(event).await
+    }
 
-        let events = self
+    /// Gift wrap a rumor for `receiver` with the pool's signer, without publishing it
+    pub async fn gift_wrap<I>(
+        &self,
+        receiver: &PublicKey,
+        rumor: UnsignedEvent,
+        extra_tags: I,
+        difficulty: u8,
+    ) -> Result<Event>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let signer = self
             .client
-            .fetch_events(combined_filter, timeout)
+            .signer()
             .await
-            .map_err(|e| Error::Transport(e.to_string()))?;
+            .map_err(|e| Error::Other(e.to_string()))?;
+        if difficulty > 0 {
+            encryption::gift_wrap_with_pow(&signer, receiver, rumor, extra_tags, difficulty).await
+        } else {
+            EventBuilder::gift_wrap(&signer, receiver, rumor, extra_tags)
+                .await
+                .map_err(|e| Error::Encryption(e.to_string()))
+        }
+    }
+
+    /// Re-send an event to a relay that refused it until we authenticate
+    async fn publish_after_auth(&self, url: &RelayUrl, event: &Event) -> std::result::Result<(), String> {
+        let deadline = tokio::time::Instant::now() + auth::AUTH_TIMEOUT;
+        match auth::wait_for_auth(&self.health, &url.to_string(), deadline).await {
+            Ok(true) => {}
+            Ok(false) => return Err("relay did not authenticate us in time".to_string()),
+            Err(e) => return Err(e.to_string()),
+        }
+
+        let output = self
+            .client
+            .send_event_to([url.clone()], event)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        match output.failed.into_values().next() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    fn stop_monitor(&self) {
+        if let Some(monitor) = self.monitor.lock().ok().and_then(|mut monitor| monitor.take()) {
+            monitor.abort();
+        }
+    }
+
+    /// Fetch stored events matching any of the filters
+    ///
+    /// nostr-sdk sends one REQ per filter; the results are merged, de-duplicated and
+    /// sorted newest first, giving the OR semantics of a multi-filter REQ.
+    pub async fn fetch_events(&self, filters: Vec<Filter>, timeout: Duration) -> Result<Vec<Event>> {
+        let mut tasks = tokio::task::JoinSet::new();
+        for filter in filters {
+            let client = self.client.clone();
+            tasks.spawn(async move { client.fetch_events(filter, timeout).await });
+        }
+
+        let mut seen = HashSet::new();
+        let mut events = Vec::new();
+
+        while let Some(result) = tasks.join_next().await {
+            let fetched = result
+                .map_err(|e| Error::Transport(e.to_string()))?
+                .map_err(|e| Error::Transport(e.to_string()))?;
+
+            for event in fetched {
+                if seen.insert(event.id) {
+                    events.push(event);
+                }
+            }
+        }
+
+        events.sort_by_key(|event| std::cmp::Reverse(event.created_at));
 
         Ok(events)
     }
 
+    /// Open a long-lived subscription for events matching any of the filters
+    ///
+    /// Events are de-duplicated across filters and relays. The subscription is closed on
+    /// relays by [`Subscription::unsubscribe`] or when the handle is dropped.
+    pub async fn subscribe(&self, filters: Vec<Filter>) -> Result<Subscription> {
+        // Listen before sending REQs so no early event is missed
+        let notifications = self.client.notifications();
+
+        let mut ids = Vec::with_capacity(filters.len());
+        for filter in filters {
+            let output = self
+                .client
+                .subscribe(filter.clone(), None)
+                .await
+                .map_err(|e| Error::Transport(e.to_string()))?;
+            if let Ok(mut subscriptions) = self.subscriptions.lock() {
+                subscriptions.insert(output.val.clone(), filter.clone());
+            }
+            ids.push(output.val);
+        }
+
+        let (tx, rx) = mpsc::channel(SUBSCRIPTION_CHANNEL_CAPACITY);
+        let task = tokio::spawn(forward_events(notifications, ids.iter().cloned().collect(), tx));
+
+        Ok(Subscription {
+            ids,
+            client: self.client.clone(),
+            subscriptions: self.subscriptions.clone(),
+            events: rx,
+            task,
+        })
+    }
+
     /// Get the underlying client
     pub fn client(&self) -> &Arc<Client> {
         &self.client
     }
 }
+
+impl Drop for RelayPool {
+    fn drop(&mut self) {
+        self.stop_monitor();
+    }
+}
+
+/// Forward pool notifications belonging to the given subscriptions into a channel
+async fn forward_events(
+    mut notifications: broadcast::Receiver<RelayPoolNotification>,
+    ids: HashSet<SubscriptionId>,
+    tx: mpsc::Sender<Event>,
+) {
+    let mut seen = BoundedSet::new(SUBSCRIPTION_SEEN_CAPACITY);
+
+    loop {
+        match notifications.recv().await {
+            Ok(RelayPoolNotification::Event { subscription_id, event, .. }) => {
+                if !ids.contains(&subscription_id) || !seen.insert(event.id) {
+                    continue;
+                }
+                if tx.send(*event).await.is_err() {
+                    break;
+                }
+            }
+            Ok(_) => {}
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                tracing::warn!("Subscription lagged behind, skipped {} notifications", skipped);
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+/// Handle to a live relay subscription
+pub struct Subscription {
+    ids: Vec<SubscriptionId>,
+    client: Arc<Client>,
+    /// The pool's subscription registry, so closed subscriptions are not restored
+    subscriptions: SubscriptionMap,
+    events: mpsc::Receiver<Event>,
+    task: JoinHandle<()>,
+}
+
+impl Subscription {
+    /// Receive the next matching event, or `None` once the pool shuts down
+    pub async fn recv(&mut self) -> Option<Event> {
+        self.events.recv().await
+    }
+
+    /// Relay subscription IDs backing this handle (one per filter)
+    pub fn ids(&self) -> &[SubscriptionId] {
+        &self.ids
+    }
+
+    /// Close the subscription on all relays
+    pub async fn unsubscribe(mut self) {
+        self.task.abort();
+        self.forget();
+        for id in std::mem::take(&mut self.ids) {
+            self.client.unsubscribe(&id).await;
+        }
+    }
+}
+
+impl Subscription {
+    /// Remove this subscription's filters from the pool's registry
+    fn forget(&self) {
+        if let Ok(mut subscriptions) = self.subscriptions.lock() {
+            for id in &self.ids {
+                subscriptions.remove(id);
+            }
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.task.abort();
+        self.forget();
+
+        if self.ids.is_empty() {
+            return;
+        }
+
+        // Best effort: close relay-side subscriptions if a runtime is still around
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            let client = self.client.clone();
+            let ids = std::mem::take(&mut self.ids);
+            handle.spawn(async move {
+                for id in ids {
+                    client.unsubscribe(&id).await;
+                }
+            });
+        }
+    }
+}
@@ -0,0 +1,354 @@
+//! File-backed keystore
+//!
+//! Each key lives in `<dir>/<name>.key`, readable only by its owner (0600), holding a
+//! NIP-49 `ncryptsec` when stored with a passphrase and an `nsec` otherwise. The public
+//! key is kept next to it in `<name>.pub` so keys can be listed without decrypting them.
+//!
+//! Configs refer to stored keys as `keystore:<name>` or `file:<path>`; see
+//! [`super::from_sk`].
+
+use crate::core::error::{Error, Result};
+use nostr_sdk::prelude::*;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Environment variable overriding the keystore directory
+pub const KEYSTORE_ENV: &str = "CVM_KEYSTORE";
+
+/// Environment variable holding the passphrase of encrypted keys
+pub const PASSPHRASE_ENV: &str = "CVM_KEY_PASSPHRASE";
+
+/// Prefix of references to keys in the default keystore
+pub const KEYSTORE_PREFIX: &str = "keystore:";
+
+/// Prefix of references to key files
+pub const FILE_PREFIX: &str = "file:";
+
+/// Bech32 prefix of NIP-49 encrypted secret keys
+const NCRYPTSEC_PREFIX: &str = "ncryptsec1";
+
+/// Directory of named key files
+#[derive(Debug, Clone)]
+pub struct Keystore {
+    dir: PathBuf,
+}
+
+impl Keystore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Keystore in `$CVM_KEYSTORE`, falling back to `~/.config/cvm/keys`
+    pub fn open_default() -> Result<Self> {
+        std::env::var_os(KEYSTORE_ENV)
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config/cvm/keys")))
+            .map(Self::new)
+            .ok_or_else(|| Error::Key(format!("No keystore directory: set {} or HOME", KEYSTORE_ENV)))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Whether a key with this name is stored
+    pub fn contains(&self, name: &str) -> bool {
+        validate_name(name).is_ok() && self.key_path(name).exists()
+    }
+
+    /// Names of stored keys with their public keys, sorted by name
+    pub fn list(&self) -> Result<Vec<(String, Option<PublicKey>)>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut keys = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("key") {
+                continue;
+            }
+            let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+            keys.push((name.to_string(), self.public_key(name)));
+        }
+        keys.sort_by(|a, b| a.0.cmp(&b.0));
+
+        Ok(keys)
+    }
+
+    /// Public key of a stored key, read without decrypting it
+    pub fn public_key(&self, name: &str) -> Option<PublicKey> {
+        validate_name(name).ok()?;
+        let contents = fs::read_to_string(self.dir.join(format!("{}.pub", name))).ok()?;
+        PublicKey::parse(contents.trim()).ok()
+    }
+
+    /// Whether a stored key is encrypted with a passphrase
+    pub fn is_encrypted(&self, name: &str) -> Result<bool> {
+        validate_name(name)?;
+        let contents = read_key_file(&self.key_path(name))?;
+        Ok(contents.trim().starts_with(NCRYPTSEC_PREFIX))
+    }
+
+    /// Generate and store a new key, encrypted if a passphrase is given
+    pub fn generate(&self, name: &str, passphrase: Option<&str>) -> Result<PublicKey> {
+        self.ensure_absent(name)?;
+        self.store(name, &Keys::generate(), passphrase)
+    }
+
+    /// Store an existing secret key (nsec, hex or `ncryptsec`)
+    ///
+    /// `passphrase` decrypts an `ncryptsec` and encrypts the stored key.
+    pub fn import(&self, name: &str, secret: &str, passphrase: Option<&str>) -> Result<PublicKey> {
+        self.ensure_absent(name)?;
+        let keys = decode(secret, passphrase)?;
+        self.store(name, &keys, passphrase)
+    }
+
+    /// Load a stored key
+    pub fn load(&self, name: &str, passphrase: Option<&str>) -> Result<Keys> {
+        validate_name(name)?;
+        let path = self.key_path(name);
+        if !path.exists() {
+            return Err(Error::Key(format!("No key named {} in {}", name, self.dir.display())));
+        }
+
+        decode(&read_key_file(&path)?, passphrase)
+    }
+
+    /// Export a stored key as an `ncryptsec` encrypted with `export_passphrase`
+    pub fn export(&self, name: &str, passphrase: Option<&str>, export_passphrase: &str) -> Result<String> {
+        let keys = self.load(name, passphrase)?;
+        encrypt(keys.secret_key(), export_passphrase)
+    }
+
+    /// Replace a stored key with a new one, returning the old and new public keys
+    ///
+    /// The old key file is kept as `<name>.key.<timestamp>` so it can be recovered. The
+    /// new key is written to `<name>.key.tmp` first, so if anything fails the old key
+    /// stays in place.
+    pub fn rotate(&self, name: &str, passphrase: Option<&str>) -> Result<(PublicKey, PublicKey)> {
+        let old = self.load(name, passphrase)?;
+        let new = Keys::generate();
+
+        let path = self.key_path(name);
+        let tmp = self.dir.join(format!("{}.key.tmp", name));
+        // Left over by an interrupted rotation
+        let _ = fs::remove_file(&tmp);
+        write_private(&tmp, &encode_secret(&new, passphrase)?)?;
+
+        let archive = self
+            .dir
+            .join(format!("{}.key.{}", name, Timestamp::now().as_u64()));
+        if let Err(e) = fs::rename(&path, &archive) {
+            let _ = fs::remove_file(&tmp);
+            return Err(e.into());
+        }
+        if let Err(e) = fs::rename(&tmp, &path) {
+            let _ = fs::rename(&archive, &path);
+            let _ = fs::remove_file(&tmp);
+            return Err(e.into());
+        }
+
+        self.write_public_key(name, &new)?;
+        Ok((old.public_key(), new.public_key()))
+    }
+
+    fn key_path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.key", name))
+    }
+
+    fn ensure_absent(&self, name: &str) -> Result<()> {
+        validate_name(name)?;
+        if self.key_path(name).exists() {
+            return Err(Error::Key(format!("A key named {} already exists", name)));
+        }
+        Ok(())
+    }
+
+    fn store(&self, name: &str, keys: &Keys, passphrase: Option<&str>) -> Result<PublicKey> {
+        let secret = encode_secret(keys, passphrase)?;
+
+        create_private_dir(&self.dir)?;
+        write_private(&self.key_path(name), &secret)?;
+
+        self.write_public_key(name, keys)?;
+        Ok(keys.public_key())
+    }
+
+    fn write_public_key(&self, name: &str, keys: &Keys) -> Result<()> {
+        let npub = keys
+            .public_key()
+            .to_bech32()
+            .map_err(|e| Error::Key(e.to_string()))?;
+        fs::write(self.dir.join(format!("{}.pub", name)), format!("{}\n", npub))?;
+        Ok(())
+    }
+}
+
+/// Contents of a key file: an `ncryptsec` with a passphrase, an `nsec` without
+fn encode_secret(keys: &Keys, passphrase: Option<&str>) -> Result<String> {
+    match passphrase {
+        Some(passphrase) => encrypt(keys.secret_key(), passphrase),
+        None => keys
+            .secret_key()
+            .to_bech32()
+            .map_err(|e| Error::Key(e.to_string())),
+    }
+}
+
+/// Decode an nsec, hex or `ncryptsec` secret key
+pub fn decode(secret: &str, passphrase: Option<&str>) -> Result<Keys> {
+    let secret = secret.trim();
+
+    if secret.starts_with(NCRYPTSEC_PREFIX) {
+        let passphrase = passphrase.ok_or_else(|| {
+            Error::Key(format!("Encrypted key needs a passphrase: set {}", PASSPHRASE_ENV))
+        })?;
+        let encrypted = EncryptedSecretKey::from_bech32(secret).map_err(|e| Error::Key(e.to_string()))?;
+        let secret_key = encrypted
+            .decrypt(passphrase)
+            .map_err(|_| Error::Key("Wrong passphrase or corrupted ncryptsec".to_string()))?;
+        return Ok(Keys::new(secret_key));
+    }
+
+    // Never include the input in the error: it may be a secret
+    Keys::parse(secret).map_err(|_| Error::Key("Invalid secret key".to_string()))
+}
+
+fn encrypt(secret_key: &SecretKey, passphrase: &str) -> Result<String> {
+    let encrypted = secret_key
+        .encrypt(passphrase)
+        .map_err(|e| Error::Key(e.to_string()))?;
+    encrypted.to_bech32().map_err(|e| Error::Key(e.to_string()))
+}
+
+/// Names become file names, so keep them to a safe alphabet
+fn validate_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::Key(format!("Invalid key name {:?}: use letters, digits, '_' and '-'", name)))
+    }
+}
+
+/// Read a key file, refusing files that other users can read
+pub(crate) fn read_key_file(path: &Path) -> Result<String> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mode = fs::metadata(path)?.permissions().mode();
+        if mode & 0o077 != 0 {
+            return Err(Error::Key(format!(
+                "{} is accessible by other users (mode {:o}): run chmod 600",
+                path.display(),
+                mode & 0o777
+            )));
+        }
+    }
+
+    Ok(fs::read_to_string(path)?)
+}
+
+fn create_private_dir(dir: &Path) -> Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(dir)?;
+    Ok(())
+}
+
+/// Write a file only its owner can read
+fn write_private(path: &Path, contents: &str) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(path)?;
+    file.write_all(contents.as_bytes())?;
+    file.write_all(b"\n")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_keystore(test: &str) -> Keystore {
+        let dir = std::env::temp_dir().join(format!("cvm-keystore-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        Keystore::new(dir)
+    }
+
+    #[test]
+    fn test_store_and_rotate() {
+        let keystore = temp_keystore("rotate");
+
+        let pubkey = keystore.generate("agent", Some("correct horse")).unwrap();
+        assert!(keystore.generate("agent", None).is_err());
+        assert_eq!(keystore.public_key("agent"), Some(pubkey));
+        assert_eq!(keystore.load("agent", Some("correct horse")).unwrap().public_key(), pubkey);
+        assert!(keystore.load("agent", Some("wrong")).is_err());
+        assert!(keystore.load("agent", None).is_err());
+
+        let (old, new) = keystore.rotate("agent", Some("correct horse")).unwrap();
+        assert_eq!(old, pubkey);
+        assert_ne!(new, pubkey);
+        assert_eq!(keystore.list().unwrap(), vec![("agent".to_string(), Some(new))]);
+
+        // A rotation that cannot write the new key leaves the current one in place
+        fs::create_dir(keystore.dir().join("agent.key.tmp")).unwrap();
+        assert!(keystore.rotate("agent", Some("correct horse")).is_err());
+        assert_eq!(keystore.load("agent", Some("correct horse")).unwrap().public_key(), new);
+        assert_eq!(keystore.public_key("agent"), Some(new));
+
+        fs::remove_dir_all(keystore.dir()).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_key_files_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let keystore = temp_keystore("private");
+        let keys = Keys::generate();
+        let nsec = keys.secret_key().to_bech32().unwrap();
+        keystore.import("imported", &nsec, None).unwrap();
+
+        let path = keystore.key_path("imported");
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        assert_eq!(keystore.load("imported", None).unwrap().public_key(), keys.public_key());
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(keystore.load("imported", None).is_err());
+
+        fs::remove_dir_all(keystore.dir()).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_names() {
+        let keystore = temp_keystore("names");
+        assert!(keystore.generate("../escape", None).is_err());
+        assert!(keystore.generate("", None).is_err());
+        assert!(!keystore.contains("../escape"));
+        assert!(keystore.public_key("../escape").is_none());
+    }
+}
@@ -1,10 +1,36 @@
 //! Nostr signer wrapper
 
+pub mod keystore;
+
+pub use keystore::Keystore;
 pub use nostr_sdk::prelude::{Keys, NostrSigner, PublicKey};
 
-/// Create a new signer from a private key (hex string or nsec)
-pub fn from_sk(sk: &str) -> Result<Keys, nostr_sdk::key::Error> {
-    Keys::parse(sk)
+use crate::core::error::Result;
+use std::path::Path;
+
+/// Create a new signer from a private key
+///
+/// Accepts an nsec or hex key, a NIP-49 `ncryptsec`, a `keystore:<name>` reference to
+/// the default [`Keystore`] or a `file:<path>` reference to a key file. Encrypted keys
+/// are decrypted with the passphrase in `$CVM_KEY_PASSPHRASE`.
+pub fn from_sk(sk: &str) -> Result<Keys> {
+    let passphrase = std::env::var(keystore::PASSPHRASE_ENV).ok();
+    from_sk_with_passphrase(sk, passphrase.as_deref())
+}
+
+/// Like [`from_sk`], with an explicit passphrase for encrypted keys
+pub fn from_sk_with_passphrase(sk: &str, passphrase: Option<&str>) -> Result<Keys> {
+    let sk = sk.trim();
+
+    if let Some(name) = sk.strip_prefix(keystore::KEYSTORE_PREFIX) {
+        return Keystore::open_default()?.load(name, passphrase);
+    }
+    if let Some(path) = sk.strip_prefix(keystore::FILE_PREFIX) {
+        let contents = keystore::read_key_file(Path::new(path))?;
+        return keystore::decode(&contents, passphrase);
+    }
+
+    keystore::decode(sk, passphrase)
 }
 
 /// Generate a new random signer
@@ -0,0 +1,338 @@
+//! In-process Nostr relay for offline tests
+//!
+//! [`TestRelay`] speaks just enough NIP-01 (`EVENT`, `REQ`, `CLOSE`, `EOSE`, `OK`) for
+//! the transports to run end to end: regular events are stored, replaceable and
+//! addressable events replace older versions, and ephemeral events are only forwarded
+//! to live subscriptions.
+
+use crate::core::error::{Error, Result};
+use futures_util::{SinkExt, StreamExt};
+use nostr_sdk::prelude::*;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, watch, RwLock};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message;
+
+/// Capacity of the live event broadcast shared by all connections
+const LIVE_CHANNEL_CAPACITY: usize = 1024;
+
+/// Relay state shared by all connections
+#[derive(Default)]
+struct RelayState {
+    events: Vec<Value>,
+    /// Number of open subscriptions across all connections
+    subscriptions: usize,
+}
+
+/// Minimal websocket relay bound to a random localhost port
+pub struct TestRelay {
+    addr: SocketAddr,
+    state: Arc<RwLock<RelayState>>,
+    /// Accepting task and the sender whose drop closes every connection, while running
+    running: Option<(JoinHandle<()>, watch::Sender<()>)>,
+}
+
+impl TestRelay {
+    /// Start a relay on `127.0.0.1` with an OS-assigned port
+    pub async fn start() -> Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(RwLock::new(RelayState::default()));
+        let running = Some(serve(listener, state.clone()));
+
+        Ok(Self { addr, state, running })
+    }
+
+    /// Close every connection and stop accepting new ones, as if the relay went down
+    ///
+    /// Stored events are kept for [`Self::restart`].
+    pub fn stop(&mut self) {
+        if let Some((task, _shutdown)) = self.running.take() {
+            task.abort();
+        }
+    }
+
+    /// Accept connections again on the same port after [`Self::stop`]
+    pub async fn restart(&mut self) -> Result<()> {
+        self.stop();
+
+        // The aborted task releases the port asynchronously
+        let mut attempts = 0;
+        let listener = loop {
+            match TcpListener::bind(self.addr).await {
+                Ok(listener) => break listener,
+                Err(e) if attempts < 50 => {
+                    tracing::debug!("Test relay port not free yet: {}", e);
+                    attempts += 1;
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        };
+        self.running = Some(serve(listener, self.state.clone()));
+
+        Ok(())
+    }
+
+    /// Websocket URL of the relay
+    pub fn url(&self) -> String {
+        format!("ws://{}", self.addr)
+    }
+
+    /// Stored (non-ephemeral) events
+    pub async fn events(&self) -> Vec<Event> {
+        self.state
+            .read()
+            .await
+            .events
+            .iter()
+            .filter_map(|event| serde_json::from_value(event.clone()).ok())
+            .collect()
+    }
+
+    /// Wait until at least `count` subscriptions are open, e.g. before publishing
+    /// ephemeral events that would otherwise be lost
+    pub async fn wait_for_subscriptions(&self, count: usize, timeout: Duration) -> Result<()> {
+        tokio::time::timeout(timeout, async {
+            while self.state.read().await.subscriptions < count {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .map_err(|_| Error::Timeout)
+    }
+}
+
+impl Drop for TestRelay {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Accept connections until the returned task is aborted and the sender dropped
+fn serve(listener: TcpListener, state: Arc<RwLock<RelayState>>) -> (JoinHandle<()>, watch::Sender<()>) {
+    let (live_tx, _) = broadcast::channel(LIVE_CHANNEL_CAPACITY);
+    let (shutdown_tx, shutdown_rx) = watch::channel(());
+
+    let task = tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let state = state.clone();
+            let live_tx = live_tx.clone();
+            let shutdown = shutdown_rx.clone();
+            tokio::spawn(async move {
+                if let Err(e) = handle_connection(stream, state, live_tx, shutdown).await {
+                    tracing::debug!("Test relay connection closed: {}", e);
+                }
+            });
+        }
+    });
+
+    (task, shutdown_tx)
+}
+
+async fn handle_connection(
+    stream: TcpStream,
+    state: Arc<RwLock<RelayState>>,
+    live_tx: broadcast::Sender<Value>,
+    mut shutdown: watch::Receiver<()>,
+) -> Result<()> {
+    let ws = tokio_tungstenite::accept_async(stream)
+        .await
+        .map_err(|e| Error::Transport(e.to_string()))?;
+    let (mut sink, mut source) = ws.split();
+    let mut live_rx = live_tx.subscribe();
+    let mut subscriptions: HashMap<String, Vec<Value>> = HashMap::new();
+
+    let result = 'connection: loop {
+        let outgoing = tokio::select! {
+            message = source.next() => {
+                let text = match message {
+                    Some(Ok(Message::Text(text))) => text.as_str().to_string(),
+                    Some(Ok(Message::Close(_))) | None => break Ok(()),
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => break Err(Error::Transport(e.to_string())),
+                };
+                handle_message(&text, &state, &live_tx, &mut subscriptions).await
+            }
+            live = live_rx.recv() => match live {
+                Ok(event) => subscriptions
+                    .iter()
+                    .filter(|(_, filters)| filters.iter().any(|filter| matches(filter, &event)))
+                    .map(|(id, _)| json!(["EVENT", id, event]))
+                    .collect(),
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => break Ok(()),
+            },
+            // The relay was stopped
+            _ = shutdown.changed() => break Ok(()),
+        };
+
+        for message in outgoing {
+            if let Err(e) = sink.send(Message::text(message.to_string())).await {
+                break 'connection Err(Error::Transport(e.to_string()));
+            }
+        }
+    };
+
+    state.write().await.subscriptions -= subscriptions.len();
+
+    result
+}
+
+/// Handle one client message, returning the replies to send
+async fn handle_message(
+    text: &str,
+    state: &Arc<RwLock<RelayState>>,
+    live_tx: &broadcast::Sender<Value>,
+    subscriptions: &mut HashMap<String, Vec<Value>>,
+) -> Vec<Value> {
+    let Ok(Value::Array(message)) = serde_json::from_str::<Value>(text) else {
+        return vec![json!(["NOTICE", "invalid: not a JSON array"])];
+    };
+
+    match (message.first().and_then(Value::as_str), message.get(1)) {
+        (Some("EVENT"), Some(event)) => {
+            let id = event["id"].clone();
+            match serde_json::from_value::<Event>(event.clone()) {
+                Ok(parsed) if parsed.verify().is_ok() => {
+                    store_event(state, event.clone()).await;
+                    let _ = live_tx.send(event.clone());
+                    vec![json!(["OK", id, true, ""])]
+                }
+                _ => vec![json!(["OK", id, false, "invalid: bad event"])],
+            }
+        }
+        (Some("REQ"), Some(Value::String(sub_id))) => {
+            let filters = message[2..].to_vec();
+            let mut replies: Vec<Value> = stored_matches(state, &filters)
+                .await
+                .into_iter()
+                .map(|event| json!(["EVENT", sub_id, event]))
+                .collect();
+            replies.push(json!(["EOSE", sub_id]));
+
+            if subscriptions.insert(sub_id.clone(), filters).is_none() {
+                state.write().await.subscriptions += 1;
+            }
+            replies
+        }
+        (Some("CLOSE"), Some(Value::String(sub_id))) => {
+            if subscriptions.remove(sub_id).is_some() {
+                state.write().await.subscriptions -= 1;
+            }
+            Vec::new()
+        }
+        _ => vec![json!(["NOTICE", "unsupported message"])],
+    }
+}
+
+/// Store an event according to its kind range (NIP-01)
+async fn store_event(state: &Arc<RwLock<RelayState>>, event: Value) {
+    let kind = event["kind"].as_u64().unwrap_or_default();
+    let replaceable = kind == 0 || kind == 3 || (10_000..20_000).contains(&kind);
+    let addressable = (30_000..40_000).contains(&kind);
+
+    if (20_000..30_000).contains(&kind) {
+        return;
+    }
+
+    let mut state = state.write().await;
+
+    if replaceable || addressable {
+        let d_tag = |event: &Value| {
+            event["tags"]
+                .as_array()
+                .and_then(|tags| tags.iter().find(|tag| tag[0] == "d"))
+                .map(|tag| tag[1].clone())
+        };
+        let same_slot = |stored: &Value| {
+            stored["kind"] == event["kind"]
+                && stored["pubkey"] == event["pubkey"]
+                && (!addressable || d_tag(stored) == d_tag(&event))
+        };
+
+        let newer_exists = state.events.iter().any(|stored| {
+            same_slot(stored) && stored["created_at"].as_u64() > event["created_at"].as_u64()
+        });
+        if newer_exists {
+            return;
+        }
+        state.events.retain(|stored| !same_slot(stored));
+    }
+
+    state.events.push(event);
+}
+
+/// Stored events matching any filter, newest first, honouring per-filter limits
+async fn stored_matches(state: &Arc<RwLock<RelayState>>, filters: &[Value]) -> Vec<Value> {
+    let state = state.read().await;
+    let mut events: Vec<&Value> = state.events.iter().collect();
+    events.sort_by_key(|event| std::cmp::Reverse(event["created_at"].as_u64()));
+
+    let mut matched: Vec<Value> = Vec::new();
+    for filter in filters {
+        let limit = filter["limit"].as_u64().map(|l| l as usize).unwrap_or(usize::MAX);
+        for event in events.iter().filter(|event| matches(filter, event)).take(limit) {
+            if !matched.iter().any(|m| m["id"] == event["id"]) {
+                matched.push((*event).clone());
+            }
+        }
+    }
+
+    matched
+}
+
+/// NIP-01 filter matching on raw JSON
+fn matches(filter: &Value, event: &Value) -> bool {
+    let Some(conditions) = filter.as_object() else {
+        return false;
+    };
+
+    let contains = |values: &Value, needle: &Value| {
+        values
+            .as_array()
+            .is_some_and(|values| values.iter().any(|value| value == needle))
+    };
+
+    conditions.iter().all(|(key, condition)| match key.as_str() {
+        "ids" => contains(condition, &event["id"]),
+        "authors" => contains(condition, &event["pubkey"]),
+        "kinds" => contains(condition, &event["kind"]),
+        "since" => event["created_at"].as_u64() >= condition.as_u64(),
+        "until" => event["created_at"].as_u64() <= condition.as_u64(),
+        tag if tag.len() == 2 && tag.starts_with('#') => event["tags"]
+            .as_array()
+            .is_some_and(|tags| {
+                tags.iter()
+                    .any(|t| t[0].as_str() == Some(&tag[1..]) && contains(condition, &t[1]))
+            }),
+        _ => true,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_matching() {
+        let event = json!({
+            "id": "aa",
+            "pubkey": "bb",
+            "kind": 25910,
+            "created_at": 100,
+            "tags": [["p", "cc"]],
+        });
+
+        assert!(matches(&json!({ "kinds": [25910], "#p": ["cc"] }), &event));
+        assert!(!matches(&json!({ "kinds": [1059] }), &event));
+        assert!(!matches(&json!({ "#p": ["dd"] }), &event));
+        assert!(!matches(&json!({ "since": 101 }), &event));
+        assert!(matches(&json!({ "authors": ["bb"], "until": 100 }), &event));
+    }
+}
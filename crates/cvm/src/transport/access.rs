@@ -0,0 +1,202 @@
+//! Client access control: allow/deny lists and per-client rate limits
+
+use crate::core::types::RateLimit;
+use nostr_sdk::prelude::*;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// How long a client refused for any reason but its rate limit goes without another reply
+const REFUSAL_REPLY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Why a client's message was refused
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Refusal {
+    /// The client is denied or not on the allowlist
+    Denied,
+    /// The client exceeded its rate limit; it may retry after the given delay
+    RateLimited(Duration),
+    /// The server already serves its maximum number of sessions
+    TooManySessions,
+}
+
+impl Refusal {
+    fn code(&self) -> i64 {
+        match self {
+            Self::Denied => -32001,
+            Self::RateLimited(_) => -32002,
+            Self::TooManySessions => -32003,
+        }
+    }
+
+    fn message(&self) -> &'static str {
+        match self {
+            Self::Denied => "Access denied",
+            Self::RateLimited(_) => "Rate limit exceeded",
+            Self::TooManySessions => "Server busy: too many sessions",
+        }
+    }
+
+    /// JSON-RPC error response refusing the request with the given id
+    pub(crate) fn error_response(&self, id: Value) -> Value {
+        let mut error = json!({ "code": self.code(), "message": self.message() });
+        if let Self::RateLimited(retry_after) = self {
+            error["data"] = json!({ "retryAfter": retry_after.as_secs_f64().ceil() as u64 });
+        }
+
+        json!({ "jsonrpc": "2.0", "id": id, "error": error })
+    }
+}
+
+impl std::fmt::Display for Refusal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.message())
+    }
+}
+
+/// Token bucket of one client
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Per-client token buckets
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+    buckets: HashMap<PublicKey, TokenBucket>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Take a token for a message from `client`, or return how long until one is available
+    pub(crate) fn check(&mut self, client: PublicKey, now: Instant) -> std::result::Result<(), Duration> {
+        let burst = f64::from(self.limit.burst.max(1));
+        let per_second = self.limit.per_second;
+
+        let bucket = self.buckets.entry(client).or_insert(TokenBucket {
+            tokens: burst,
+            updated: now,
+        });
+
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * per_second).min(burst);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else if per_second > 0.0 {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / per_second))
+        } else {
+            Err(Duration::MAX)
+        }
+    }
+
+    /// Forget clients whose buckets have refilled completely
+    pub(crate) fn prune(&mut self, now: Instant) {
+        let burst = f64::from(self.limit.burst.max(1));
+        let per_second = self.limit.per_second;
+
+        self.buckets.retain(|_, bucket| {
+            let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens + elapsed * per_second < burst
+        });
+    }
+}
+
+/// Refused clients that were answered recently
+///
+/// A refused client gets one error reply per retry window, so it cannot make the server
+/// sign and publish a reply to every event it sends.
+#[derive(Debug, Default)]
+pub(crate) struct RefusalReplies {
+    quiet_until: HashMap<PublicKey, Instant>,
+}
+
+impl RefusalReplies {
+    /// Whether to answer a refusal of `client`; if so, it gets no other until it may retry
+    pub(crate) fn should_reply(&mut self, client: PublicKey, refusal: Refusal, now: Instant) -> bool {
+        if self.quiet_until.get(&client).is_some_and(|until| now < *until) {
+            return false;
+        }
+
+        let window = match refusal {
+            Refusal::RateLimited(retry_after) => retry_after.min(REFUSAL_REPLY_INTERVAL),
+            Refusal::Denied | Refusal::TooManySessions => REFUSAL_REPLY_INTERVAL,
+        };
+        self.quiet_until.insert(client, now + window);
+        true
+    }
+
+    /// Forget clients whose window has passed
+    pub(crate) fn prune(&mut self, now: Instant) {
+        self.quiet_until.retain(|_, until| now < *until);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let mut limiter = RateLimiter::new(RateLimit::new(2, 1.0));
+        let client = Keys::generate().public_key();
+        let other = Keys::generate().public_key();
+        let start = Instant::now();
+
+        assert!(limiter.check(client, start).is_ok());
+        assert!(limiter.check(client, start).is_ok());
+        let retry_after = limiter.check(client, start).unwrap_err();
+        assert_eq!(retry_after, Duration::from_secs(1));
+
+        // Other clients have their own bucket
+        assert!(limiter.check(other, start).is_ok());
+
+        // Tokens refill over time
+        assert!(limiter.check(client, start + Duration::from_secs(1)).is_ok());
+        assert!(limiter.check(client, start + Duration::from_secs(1)).is_err());
+
+        limiter.prune(start + Duration::from_secs(10));
+        assert!(limiter.buckets.is_empty());
+    }
+
+    #[test]
+    fn test_refusal_replies() {
+        let mut replies = RefusalReplies::default();
+        let client = Keys::generate().public_key();
+        let start = Instant::now();
+        let rate_limited = Refusal::RateLimited(Duration::from_secs(2));
+
+        assert!(replies.should_reply(client, rate_limited, start));
+        assert!(!replies.should_reply(client, rate_limited, start + Duration::from_secs(1)));
+        assert!(replies.should_reply(client, Refusal::Denied, start + Duration::from_secs(2)));
+        assert!(!replies.should_reply(client, Refusal::Denied, start + Duration::from_secs(30)));
+
+        // A client that can never retry is still answered once per interval
+        let other = Keys::generate().public_key();
+        assert!(replies.should_reply(other, Refusal::RateLimited(Duration::MAX), start));
+
+        replies.prune(start + Duration::from_secs(120));
+        assert!(replies.quiet_until.is_empty());
+    }
+
+    #[test]
+    fn test_refusal_response() {
+        let response = Refusal::RateLimited(Duration::from_millis(1500)).error_response(json!(3));
+        assert_eq!(response["id"], 3);
+        assert_eq!(response["error"]["code"], -32002);
+        assert_eq!(response["error"]["data"]["retryAfter"], 2);
+
+        let response = Refusal::Denied.error_response(json!("a"));
+        assert!(response["error"].get("data").is_none());
+    }
+}
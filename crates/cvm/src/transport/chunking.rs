@@ -0,0 +1,357 @@
+//! Chunked transfer of messages too large for a single relay event
+//!
+//! A large JSON-RPC message is split into ordered slices, each published as its own
+//! ContextVM event tagged `["chunk", <transfer id>, <index>, <total>, <sha256>]`. The
+//! receiver collects the slices per sender and transfer, checks the hash of the
+//! reassembled message and drops transfers that do not complete in time. Open transfers
+//! are capped per sender and overall, in count and in buffered bytes.
+
+use crate::core::{
+    constants::*, error::{Error, Result},
+};
+use nostr_sdk::hashes::{sha256::Hash as Sha256Hash, Hash};
+use nostr_sdk::prelude::*;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// How long a partially received message is kept before it is discarded
+const CHUNK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Smallest slice a message is split into
+const MIN_CHUNK_SIZE: usize = 64;
+
+/// Most chunks a message within [`MAX_MESSAGE_SIZE`] can need; larger `total`s are refused
+/// before anything is allocated for them
+const MAX_CHUNKS: usize = MAX_MESSAGE_SIZE / MIN_CHUNK_SIZE;
+
+/// Messages a single sender may have partially received at once
+const MAX_PARTIALS_PER_SENDER: usize = 8;
+
+/// Messages partially received at once across all senders
+const MAX_PARTIALS: usize = 64;
+
+/// Bytes buffered for partially received messages across all senders
+const MAX_BUFFERED_BYTES: usize = 16 * MAX_MESSAGE_SIZE;
+
+/// Chunk metadata carried in the `chunk` tag
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Chunk {
+    pub transfer_id: String,
+    pub index: usize,
+    pub total: usize,
+    pub sha256: String,
+}
+
+impl Chunk {
+    /// Find and parse the chunk tag, `None` if the event is not a chunk
+    pub(crate) fn from_tags<'a, I>(event_tags: I) -> Option<Result<Self>>
+    where
+        I: IntoIterator<Item = &'a Tag>,
+    {
+        let tag = event_tags
+            .into_iter()
+            .find(|tag| tag.as_slice().first().map(String::as_str) == Some(tags::CHUNK))?;
+
+        let parsed = match tag.as_slice() {
+            [_, transfer_id, index, total, sha256, ..] => match (index.parse(), total.parse()) {
+                (Ok(index), Ok(total)) if index < total && total <= MAX_CHUNKS => Ok(Self {
+                    transfer_id: transfer_id.clone(),
+                    index,
+                    total,
+                    sha256: sha256.clone(),
+                }),
+                _ => Err(Error::InvalidMessage("Invalid chunk index".to_string())),
+            },
+            _ => Err(Error::InvalidMessage("Malformed chunk tag".to_string())),
+        };
+
+        Some(parsed)
+    }
+
+    fn to_tag(&self) -> Tag {
+        Tag::custom(
+            TagKind::custom(tags::CHUNK),
+            [
+                self.transfer_id.clone(),
+                self.index.to_string(),
+                self.total.to_string(),
+                self.sha256.clone(),
+            ],
+        )
+    }
+}
+
+/// Split a message into event contents, tagging each slice when chunking is needed
+///
+/// Fails if the message exceeds [`MAX_MESSAGE_SIZE`].
+pub(crate) fn split_message(content: &str, chunk_size: usize) -> Result<Vec<(String, Option<Tag>)>> {
+    check_size(content.len())?;
+
+    if content.len() <= chunk_size {
+        return Ok(vec![(content.to_string(), None)]);
+    }
+
+    // Split on character boundaries so every slice is valid UTF-8
+    let chunk_size = chunk_size.max(MIN_CHUNK_SIZE);
+    let mut slices = Vec::new();
+    let mut start = 0;
+    while start < content.len() {
+        let mut end = (start + chunk_size).min(content.len());
+        while !content.is_char_boundary(end) {
+            end -= 1;
+        }
+        slices.push(&content[start..end]);
+        start = end;
+    }
+
+    let transfer_id = new_transfer_id();
+    let sha256 = sha256_hex(content);
+    let total = slices.len();
+
+    Ok(slices
+        .into_iter()
+        .enumerate()
+        .map(|(index, slice)| {
+            let chunk = Chunk {
+                transfer_id: transfer_id.clone(),
+                index,
+                total,
+                sha256: sha256.clone(),
+            };
+            (slice.to_string(), Some(chunk.to_tag()))
+        })
+        .collect())
+}
+
+/// Reject messages larger than [`MAX_MESSAGE_SIZE`]
+pub(crate) fn check_size(size: usize) -> Result<()> {
+    if size > MAX_MESSAGE_SIZE {
+        return Err(Error::InvalidMessage(format!(
+            "Message of {} bytes exceeds the {} byte limit",
+            size, MAX_MESSAGE_SIZE
+        )));
+    }
+    Ok(())
+}
+
+fn sha256_hex(content: &str) -> String {
+    Sha256Hash::hash(content.as_bytes()).to_string()
+}
+
+/// Unguessable transfer ID, so others cannot inject slices into our transfers
+fn new_transfer_id() -> String {
+    Keys::generate().public_key().to_hex()
+}
+
+/// A message that is still being received
+struct Partial {
+    chunk: Chunk,
+    parts: Vec<Option<String>>,
+    received: usize,
+    size: usize,
+    /// Event ID of the final chunk, used to correlate replies
+    last_event_id: Option<EventId>,
+    started: Instant,
+}
+
+/// Reassembles chunked messages per sender
+#[derive(Default)]
+pub(crate) struct ChunkAssembler {
+    partials: HashMap<(PublicKey, String), Partial>,
+}
+
+impl ChunkAssembler {
+    /// Add a received chunk, returning the full message and the ID of its final chunk
+    /// once every slice has arrived
+    pub(crate) fn add(
+        &mut self,
+        sender: PublicKey,
+        event_id: EventId,
+        chunk: Chunk,
+        data: String,
+    ) -> Result<Option<(String, EventId)>> {
+        self.partials
+            .retain(|_, partial| partial.started.elapsed() < CHUNK_TIMEOUT);
+
+        let key = (sender, chunk.transfer_id.clone());
+        let buffered: usize = self.partials.values().map(|partial| partial.size).sum();
+        if !self.partials.contains_key(&key) {
+            let in_progress = self.partials.keys().filter(|(pubkey, _)| *pubkey == sender).count();
+            if in_progress >= MAX_PARTIALS_PER_SENDER {
+                return Err(Error::InvalidMessage(format!(
+                    "{} chunked messages already in progress from {}",
+                    in_progress,
+                    sender.to_hex()
+                )));
+            }
+            if self.partials.len() >= MAX_PARTIALS || buffered >= MAX_BUFFERED_BYTES {
+                return Err(Error::InvalidMessage("Too many chunked messages in progress".to_string()));
+            }
+        }
+
+        let partial = self.partials.entry(key.clone()).or_insert_with(|| Partial {
+            parts: vec![None; chunk.total],
+            chunk: chunk.clone(),
+            received: 0,
+            size: 0,
+            last_event_id: None,
+            started: Instant::now(),
+        });
+
+        if partial.chunk.total != chunk.total || partial.chunk.sha256 != chunk.sha256 {
+            self.partials.remove(&key);
+            return Err(Error::InvalidMessage("Inconsistent chunk metadata".to_string()));
+        }
+
+        if partial.parts[chunk.index].is_some() {
+            return Ok(None);
+        }
+
+        partial.size += data.len();
+        if let Err(e) = check_size(partial.size) {
+            self.partials.remove(&key);
+            return Err(e);
+        }
+        if buffered + data.len() > MAX_BUFFERED_BYTES {
+            self.partials.remove(&key);
+            return Err(Error::InvalidMessage("Too much chunked data in progress".to_string()));
+        }
+
+        if chunk.index + 1 == chunk.total {
+            partial.last_event_id = Some(event_id);
+        }
+        partial.parts[chunk.index] = Some(data);
+        partial.received += 1;
+
+        if partial.received < partial.chunk.total {
+            return Ok(None);
+        }
+
+        let Some(partial) = self.partials.remove(&key) else {
+            return Ok(None);
+        };
+        let content: String = partial.parts.into_iter().flatten().collect();
+
+        if sha256_hex(&content) != partial.chunk.sha256 {
+            return Err(Error::InvalidMessage("Chunked message hash mismatch".to_string()));
+        }
+
+        Ok(Some((content, partial.last_event_id.unwrap_or(event_id))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunks_of(content: &str, chunk_size: usize) -> Vec<(String, Chunk)> {
+        split_message(content, chunk_size)
+            .unwrap()
+            .into_iter()
+            .map(|(data, tag)| {
+                let tag = tag.unwrap();
+                (data, Chunk::from_tags([&tag]).unwrap().unwrap())
+            })
+            .collect()
+    }
+
+    fn event_id() -> EventId {
+        EventBuilder::new(Kind::TextNote, "")
+            .sign_with_keys(&Keys::generate())
+            .unwrap()
+            .id
+    }
+
+    #[test]
+    fn test_small_message_is_not_chunked() {
+        let parts = split_message("{}", 1024).unwrap();
+        assert_eq!(parts.len(), 1);
+        assert!(parts[0].1.is_none());
+    }
+
+    #[test]
+    fn test_reassemble_out_of_order() {
+        let keys = Keys::generate();
+        let content = "ünïcödé payload ".repeat(100);
+        let mut chunks = chunks_of(&content, 64);
+        let total = chunks.len();
+        assert!(total > 1);
+        chunks.reverse();
+
+        // The first chunk arrives last; its event ID is not the correlation ID
+        let final_chunk_id = event_id();
+        let mut assembler = ChunkAssembler::default();
+        let mut result = None;
+        for (received, (data, chunk)) in chunks.into_iter().enumerate() {
+            let id = if chunk.index + 1 == total { final_chunk_id } else { event_id() };
+            let added = assembler.add(keys.public_key(), id, chunk, data).unwrap();
+            if received + 1 < total {
+                assert!(added.is_none());
+            } else {
+                result = added;
+            }
+        }
+
+        assert_eq!(result, Some((content, final_chunk_id)));
+    }
+
+    #[test]
+    fn test_hash_mismatch_is_rejected() {
+        let keys = Keys::generate();
+        let content = "a".repeat(200);
+        let chunks = chunks_of(&content, 64);
+        let last = chunks.len() - 1;
+
+        let mut assembler = ChunkAssembler::default();
+        let mut outcome = Ok(None);
+        for (index, (data, chunk)) in chunks.into_iter().enumerate() {
+            let data = if index == last { data.replace('a', "b") } else { data };
+            outcome = assembler.add(keys.public_key(), event_id(), chunk, data);
+        }
+
+        assert!(outcome.is_err());
+    }
+
+    #[test]
+    fn test_chunk_limits() {
+        let huge = Tag::custom(
+            TagKind::custom(tags::CHUNK),
+            ["transfer", "0", "1000000000000", "hash"],
+        );
+        assert!(Chunk::from_tags([&huge]).unwrap().is_err());
+
+        let keys = Keys::generate();
+        let mut assembler = ChunkAssembler::default();
+        let chunk = |transfer: usize| Chunk {
+            transfer_id: transfer.to_string(),
+            index: 0,
+            total: 2,
+            sha256: String::new(),
+        };
+        for transfer in 0..MAX_PARTIALS_PER_SENDER {
+            let added = assembler.add(keys.public_key(), event_id(), chunk(transfer), "a".to_string());
+            assert!(added.unwrap().is_none());
+        }
+        let added = assembler.add(keys.public_key(), event_id(), chunk(MAX_PARTIALS_PER_SENDER), "a".to_string());
+        assert!(added.is_err());
+
+        // Other senders are not affected
+        let added = assembler.add(Keys::generate().public_key(), event_id(), chunk(0), "a".to_string());
+        assert!(added.unwrap().is_none());
+
+        // Until every sender together reaches the global limit
+        let mut assembler = ChunkAssembler::default();
+        for _ in 0..MAX_PARTIALS {
+            let added = assembler.add(Keys::generate().public_key(), event_id(), chunk(0), "a".to_string());
+            assert!(added.unwrap().is_none());
+        }
+        let added = assembler.add(Keys::generate().public_key(), event_id(), chunk(0), "a".to_string());
+        assert!(added.is_err());
+    }
+
+    #[test]
+    fn test_oversized_message_is_rejected() {
+        let content = "a".repeat(MAX_MESSAGE_SIZE + 1);
+        assert!(split_message(&content, 1024).is_err());
+    }
+}
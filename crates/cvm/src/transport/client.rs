@@ -3,33 +3,189 @@
 use crate::core::{
     constants::*, error::{Error, Result}, types::*,
 };
-use crate::relay::RelayPool;
+use crate::blob::{self, BlobStore};
+use crate::metrics::{self, DropReason};
+use crate::relay::{RelayHealth, RelayPool, Subscription};
+use super::chunking::{self, Chunk, ChunkAssembler};
+use super::freshness;
+use super::outbox::{EntryOwner, Outbox};
 use nostr_sdk::prelude::*;
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::RwLock;
+use tokio::sync::{broadcast, oneshot, Mutex, RwLock};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tracing::{field, Instrument, Span};
+
+/// How long to wait for relays when fetching server metadata
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Capacity of the channel behind [`NostrClientTransport::notifications`]
+const NOTIFICATION_CHANNEL_CAPACITY: usize = 100;
+
+/// How often the outbox checks whether queued messages can be sent
+const OUTBOX_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A message a server sent on its own rather than in response to a request
+///
+/// Usually a JSON-RPC notification such as `notifications/progress`.
+#[derive(Debug, Clone)]
+pub struct ServerNotification {
+    pub server_pubkey: PublicKey,
+    /// JSON-RPC message
+    pub content: String,
+    /// Request event the notification relates to, if any
+    pub request_event_id: Option<EventId>,
+}
+
+/// A request awaiting its response
+struct PendingRequest {
+    sender: oneshot::Sender<Result<UnsignedEvent>>,
+    /// Server the request was sent to; only it may answer
+    server_pubkey: PublicKey,
+    deadline: Instant,
+}
+
+/// Requests awaiting a response, keyed by the request event ID
+type PendingRequests = Arc<RwLock<HashMap<EventId, PendingRequest>>>;
+
+/// What a server's announcement says about the messages it accepts
+#[derive(Debug, Clone, Copy, Default)]
+struct ServerRequirements {
+    /// `None` when the server has not announced itself
+    supports_encryption: Option<bool>,
+    /// NIP-13 difficulty of the events the server receives
+    min_pow: u8,
+}
+
+/// How messages to a server are sent
+#[derive(Debug, Clone, Copy)]
+struct Delivery {
+    use_encryption: bool,
+    /// Difficulty mined into the outer event: the gift wrap, or the message itself
+    pow: u8,
+    mode: DeliveryMode,
+}
 
 /// Client-side transport configuration
+#[derive(Debug, Clone)]
 pub struct NostrClientTransportConfig {
     pub relay_urls: Vec<String>,
+    /// Relays among `relay_urls` that require NIP-42 authentication
+    pub auth_relay_urls: Vec<String>,
     pub encryption_mode: EncryptionMode,
+    /// Default time to wait for a response, see [`RequestOptions`]
+    pub request_timeout: Duration,
+    /// Messages larger than this many bytes are sent as chunk events
+    pub chunk_size: usize,
+    /// Add a NIP-40 expiration this far in the future to outgoing messages
+    pub message_ttl: Option<Duration>,
+    /// Default handling of messages no relay accepts, see [`RequestOptions`]
+    pub delivery_mode: DeliveryMode,
+    /// Persist queued notifications in this directory so they survive restarts
+    ///
+    /// Queued requests are kept in memory only, since nobody waits for their
+    /// responses after a restart.
+    pub outbox_dir: Option<PathBuf>,
+    /// Drop queued messages no relay accepted within this time
+    ///
+    /// Servers ignore messages older than their replay window, so waiting longer than
+    /// that is pointless.
+    pub outbox_max_age: Duration,
+    /// Download blobs referenced by responses from here and inline their payloads;
+    /// `initialize` requests then advertise the `contextvm/blob` capability
+    pub blob_store: Option<Arc<dyn BlobStore>>,
+    /// Refuse to send to servers announcing a higher NIP-13 difficulty than this
+    pub max_pow: u8,
 }
 
 impl Default for NostrClientTransportConfig {
     fn default() -> Self {
         Self {
             relay_urls: vec!["wss://relay.damus.io".to_string()],
+            auth_relay_urls: Vec::new(),
             encryption_mode: EncryptionMode::Optional,
+            request_timeout: Duration::from_secs(30),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            message_ttl: None,
+            delivery_mode: DeliveryMode::FailFast,
+            outbox_dir: None,
+            outbox_max_age: Duration::from_secs(DEFAULT_OUTBOX_MAX_AGE_SECS),
+            blob_store: None,
+            max_pow: DEFAULT_MAX_POW,
+        }
+    }
+}
+
+/// Per-request options for [`NostrClientTransport::send_request_with`]
+#[derive(Debug, Clone, Default)]
+pub struct RequestOptions {
+    /// Time to wait for the response, overriding the configured `request_timeout`
+    pub timeout: Option<Duration>,
+    /// Absolute deadline for the response; the earlier of deadline and timeout applies
+    pub deadline: Option<Instant>,
+    /// Handling of the request if no relay accepts it, overriding the configured
+    /// `delivery_mode`. Queued requests still fail once the response deadline passes.
+    pub delivery_mode: Option<DeliveryMode>,
+}
+
+impl RequestOptions {
+    /// Options with a custom timeout
+    pub fn with_timeout(timeout: Duration) -> Self {
+        Self {
+            timeout: Some(timeout),
+            ..Default::default()
+        }
+    }
+
+    /// Options with an absolute deadline
+    pub fn with_deadline(deadline: Instant) -> Self {
+        Self {
+            deadline: Some(deadline),
+            ..Default::default()
+        }
+    }
+
+    /// Options with a custom delivery mode
+    pub fn with_delivery_mode(delivery_mode: DeliveryMode) -> Self {
+        Self {
+            delivery_mode: Some(delivery_mode),
+            ..Default::default()
+        }
+    }
+
+    fn resolve_deadline(&self, default_timeout: Duration) -> Instant {
+        let timeout_deadline = Instant::now() + self.timeout.unwrap_or(default_timeout);
+
+        match self.deadline {
+            Some(deadline) => deadline.min(timeout_deadline),
+            None => timeout_deadline,
         }
     }
 }
 
 /// Client-side Nostr transport
+///
+/// Cloning is cheap and yields a handle to the same relay pool and pending requests.
+#[derive(Clone)]
 pub struct NostrClientTransport {
     relay_pool: Arc<RelayPool>,
-    config: NostrClientTransportConfig,
-    pending_requests: Arc<RwLock<HashMap<EventId, tokio::sync::oneshot::Sender<UnsignedEvent>>>>,
+    config: Arc<NostrClientTransportConfig>,
+    pending_requests: PendingRequests,
+    /// Requirements learned from server announcements
+    server_requirements: Arc<RwLock<HashMap<PublicKey, ServerRequirements>>>,
+    /// Partially received chunked responses
+    chunks: Arc<Mutex<ChunkAssembler>>,
+    /// Task receiving responses, started by [`Self::connect`]
+    listener: Arc<Mutex<Option<JoinHandle<()>>>>,
+    /// Server notifications, see [`Self::notifications`]
+    notifications: broadcast::Sender<ServerNotification>,
+    /// Messages waiting for a relay to accept them
+    outbox: Arc<Mutex<Outbox>>,
+    /// Task sending queued messages, started by [`Self::connect`]
+    flusher: Arc<Mutex<Option<JoinHandle<()>>>>,
 }
 
 impl NostrClientTransport {
@@ -42,15 +198,25 @@ impl NostrClientTransport {
         T: IntoNostrSigner,
     {
         let relay_pool = Arc::new(RelayPool::new(signer).await?);
+        relay_pool.require_auth(&config.auth_relay_urls).await?;
+        let outbox = Outbox::open(config.outbox_dir.clone(), config.outbox_max_age)?;
 
         Ok(Self {
             relay_pool,
-            config,
+            config: Arc::new(config),
             pending_requests: Arc::new(RwLock::new(HashMap::new())),
+            server_requirements: Arc::new(RwLock::new(HashMap::new())),
+            chunks: Arc::new(Mutex::new(ChunkAssembler::default())),
+            listener: Arc::new(Mutex::new(None)),
+            notifications: broadcast::channel(NOTIFICATION_CHANNEL_CAPACITY).0,
+            outbox: Arc::new(Mutex::new(outbox)),
+            flusher: Arc::new(Mutex::new(None)),
         })
     }
 
     /// Connect to relays and start listening
+    ///
+    /// Also starts sending queued messages, including those persisted by an earlier run.
     pub async fn connect(&self) -> Result<()> {
         self.relay_pool.connect(&self.config.relay_urls).await?;
 
@@ -63,132 +229,884 @@ impl NostrClientTransport {
             .await
             .map_err(|e| Error::Other(e.to_string()))?;
 
-        // Subscribe to responses (both regular and encrypted)
-        let filter = Filter::new()
-            .kinds(vec![
+        // Subscribe to responses. Under `Required` plaintext replies are still received so
+        // that a server which cannot encrypt fails the request instead of timing it out.
+        let kinds = match self.config.encryption_mode {
+            EncryptionMode::Disabled => vec![Kind::from(CTXVM_MESSAGES_KIND)],
+            EncryptionMode::Optional | EncryptionMode::Required => vec![
                 Kind::from(CTXVM_MESSAGES_KIND),
                 Kind::from(GIFT_WRAP_KIND),
-            ])
-            .pubkey(pubkey);
+            ],
+        };
+        let filter = Filter::new().kinds(kinds).pubkey(pubkey);
 
-        client
-            .subscribe(filter, None)
-            .await
-            .map_err(|e| Error::Transport(e.to_string()))?;
+        let subscription = self.relay_pool.subscribe(vec![filter]).await?;
 
         // Start listening for responses
-        let pending_requests = self.pending_requests.clone();
-        let client_clone = client.clone();
+        let transport = self.clone();
 
-        tokio::spawn(async move {
-            Self::handle_responses(client_clone, pending_requests).await;
+        let listener = tokio::spawn(async move {
+            transport.handle_responses(subscription).await;
         });
+        if let Some(previous) = self.listener.lock().await.replace(listener) {
+            previous.abort();
+        }
+
+        let transport = self.clone();
+        let flusher = tokio::spawn(async move {
+            transport.flush_outbox().await;
+        });
+        if let Some(previous) = self.flusher.lock().await.replace(flusher) {
+            previous.abort();
+        }
 
         Ok(())
     }
 
-    async fn handle_responses(
-        client: Arc<Client>,
-        pending_requests: Arc<RwLock<HashMap<EventId, tokio::sync::oneshot::Sender<UnsignedEvent>>>>,
-    ) {
-        let mut notifications = client.notifications();
+    /// Connection health of each relay
+    pub async fn relay_status(&self) -> Vec<RelayHealth> {
+        self.relay_pool.status().await
+    }
 
-        while let Ok(notification) = notifications.recv().await {
-            if let RelayPoolNotification::Event { event, .. } = notification {
-                Self::handle_response(*event, &pending_requests, &client).await;
-            }
+    /// Number of messages waiting in the outbox for a relay to accept them
+    pub async fn queued_messages(&self) -> usize {
+        self.outbox.lock().await.len()
+    }
+
+    /// Stop listening and disconnect from relays
+    ///
+    /// Pending requests fail with [`Error::Cancelled`], and those still queued in the
+    /// outbox are dropped; queued notifications are kept.
+    pub async fn disconnect(&self) -> Result<()> {
+        if let Some(listener) = self.listener.lock().await.take() {
+            listener.abort();
+        }
+        if let Some(flusher) = self.flusher.lock().await.take() {
+            flusher.abort();
+        }
+        self.pending_requests.write().await.clear();
+        self.outbox.lock().await.discard_requests()?;
+
+        self.relay_pool.disconnect().await
+    }
+
+    async fn handle_responses(&self, mut subscription: Subscription) {
+        while let Some(event) = subscription.recv().await {
+            self.handle_response(event).await;
         }
     }
 
-    async fn handle_response(
-        event: Event,
-        pending_requests: &Arc<RwLock<HashMap<EventId, tokio::sync::oneshot::Sender<UnsignedEvent>>>>,
-        client: &Arc<Client>,
-    ) {
-        // Unwrap gift wrap if needed
-        let actual_event = if event.kind == Kind::from(GIFT_WRAP_KIND) {
+    async fn handle_response(&self, event: Event) {
+        let client = self.relay_pool.client();
+        let encryption_mode = self.config.encryption_mode;
+        let is_encrypted = event.kind == Kind::from(GIFT_WRAP_KIND);
+
+        // Unwrap gift wrap if needed, keeping the seal author for origin checks
+        let (actual_event, seal_author) = if is_encrypted {
             match client.as_ref().unwrap_gift_wrap(&event).await {
-                Ok(unwrapped) => unwrapped.rumor,
+                Ok(unwrapped) => (unwrapped.rumor, Some(unwrapped.sender)),
                 Err(err) => {
                     tracing::error!("Failed to unwrap gift wrap: {}", err);
+                    metrics::global().decrypt_failures.inc();
                     return;
                 }
             }
         } else {
+            if let Err(err) = event.verify() {
+                tracing::warn!("Dropping response with invalid signature {}: {}", event.id, err);
+                metrics::global().record_drop(DropReason::Forged);
+                return;
+            }
+
             // Convert Event to UnsignedEvent for consistency
-            UnsignedEvent::new(
+            let rumor = UnsignedEvent::new(
                 event.pubkey,
                 event.created_at,
                 event.kind,
                 event.tags.clone(),
                 event.content.clone(),
-            )
+            );
+            (rumor, None)
         };
 
+        // The rumor is unsigned: only trust its author if the seal was signed by it
+        if let Some(seal_author) = &seal_author {
+            if seal_author != &actual_event.pubkey {
+                tracing::warn!(
+                    "Dropping gift wrap whose seal author {} does not match rumor author {}",
+                    seal_author.to_hex(),
+                    actual_event.pubkey.to_hex()
+                );
+                metrics::global().record_drop(DropReason::Forged);
+                return;
+            }
+        }
+
+        if freshness::is_expired(event.tags.iter().chain(actual_event.tags.iter())) {
+            tracing::debug!("Dropping expired message from {}", actual_event.pubkey.to_hex());
+            metrics::global().record_drop(DropReason::Expired);
+            return;
+        }
+
         // Find the request event ID in tags
         let request_id = actual_event.tags.iter().find_map(|tag| {
             if let Some(TagStandard::Event { event_id, .. }) = tag.as_standardized() {
-                Some(event_id)
+                Some(*event_id)
             } else {
                 None
             }
         });
 
+        // A message about a pending request must come from the server it was sent to
         if let Some(request_id) = request_id {
-            let mut pending = pending_requests.write().await;
-            if let Some(sender) = pending.remove(&request_id) {
-                let _ = sender.send(actual_event);
+            let expected_server = self
+                .pending_requests
+                .read()
+                .await
+                .get(&request_id)
+                .map(|pending| pending.server_pubkey);
+            if let Some(expected_server) = expected_server {
+                if let Err(err) = verify_response_origin(&expected_server, &actual_event, seal_author.as_ref()) {
+                    // Leave the request pending on a spoofed reply: the real one may still arrive
+                    tracing::warn!("Dropping message about request {}: {}", request_id, err);
+                    metrics::global().record_drop(DropReason::Forged);
+                    return;
+                }
             }
         }
+
+        let mut actual_event = actual_event;
+        match Chunk::from_tags(actual_event.tags.iter()) {
+            Some(chunk) => {
+                let content = std::mem::take(&mut actual_event.content);
+                let event_id = actual_event.id.unwrap_or(event.id);
+                let added = match chunk {
+                    Ok(chunk) => self.chunks.lock().await.add(actual_event.pubkey, event_id, chunk, content),
+                    Err(err) => Err(err),
+                };
+                match added {
+                    Ok(Some((message, _))) => actual_event.content = message,
+                    Ok(None) => return,
+                    Err(err) => {
+                        tracing::warn!("Dropping chunk from {}: {}", actual_event.pubkey.to_hex(), err);
+                        metrics::global().record_drop(DropReason::Invalid);
+                        return;
+                    }
+                }
+            }
+            None => {
+                if let Err(err) = chunking::check_size(actual_event.content.len()) {
+                    tracing::warn!("Dropping message from {}: {}", actual_event.pubkey.to_hex(), err);
+                    metrics::global().record_drop(DropReason::Invalid);
+                    return;
+                }
+            }
+        }
+
+        // Messages carrying a method are notifications (or requests) from the server,
+        // everything else answers one of our requests
+        if is_server_message(&actual_event.content) {
+            if !encryption_mode.accepts(is_encrypted) {
+                tracing::warn!(
+                    "Dropping {} notification from {} (encryption mode: {:?})",
+                    if is_encrypted { "encrypted" } else { "plaintext" },
+                    actual_event.pubkey.to_hex(),
+                    encryption_mode
+                );
+                metrics::global().record_drop(DropReason::EncryptionMode);
+                return;
+            }
+
+            // Nobody listening is fine: notifications are informational
+            metrics::global().notifications_received.inc();
+            let _ = self.notifications.send(ServerNotification {
+                server_pubkey: actual_event.pubkey,
+                content: actual_event.content,
+                request_event_id: request_id,
+            });
+            return;
+        }
+
+        let Some(request_id) = request_id else {
+            tracing::debug!("Dropping response without request reference from {}", actual_event.pubkey.to_hex());
+            metrics::global().record_drop(DropReason::Unmatched);
+            return;
+        };
+
+        let pending = self.pending_requests.write().await.remove(&request_id);
+        let Some(PendingRequest { sender, .. }) = pending else {
+            // Late replies to requests that timed out or were cancelled end up here
+            tracing::debug!("Dropping response to unknown request {}", request_id);
+            metrics::global().record_drop(DropReason::Unmatched);
+            return;
+        };
+
+        let response = if encryption_mode.accepts(is_encrypted) {
+            Ok(actual_event)
+        } else if encryption_mode == EncryptionMode::Required {
+            tracing::warn!("Server replied in plaintext to request {}", request_id);
+            Err(Error::EncryptionRequired)
+        } else {
+            Err(Error::Protocol("Received encrypted response with encryption disabled".to_string()))
+        };
+        let _ = sender.send(response);
     }
 
-    /// Send a request to a server
+    /// Subscribe to notifications sent by servers
+    ///
+    /// Receives `notifications/progress`, `notifications/message` logging and
+    /// list-changed notifications from every server this client talks to. Only messages
+    /// arriving after the call are received.
+    pub fn notifications(&self) -> broadcast::Receiver<ServerNotification> {
+        self.notifications.subscribe()
+    }
+
+    /// Fetch the latest announcement of a server
+    ///
+    /// The announced encryption support and PoW difficulty are remembered and used when
+    /// sending requests.
+    pub async fn fetch_announcement(&self, server_pubkey: &PublicKey) -> Result<Option<ServerAnnouncement>> {
+        let filter = Filter::new()
+            .kind(Kind::from(SERVER_ANNOUNCEMENT_KIND))
+            .author(*server_pubkey)
+            .limit(1);
+
+        let events = self.relay_pool.fetch_events(vec![filter], FETCH_TIMEOUT).await?;
+        let announcement = events.iter().find_map(ServerAnnouncement::from_event);
+
+        let requirements = announcement
+            .as_ref()
+            .map(|announcement| ServerRequirements {
+                supports_encryption: Some(announcement.supports_encryption),
+                min_pow: announcement.min_pow.unwrap_or(0),
+            })
+            .unwrap_or_default();
+        self.server_requirements
+            .write()
+            .await
+            .insert(*server_pubkey, requirements);
+
+        Ok(announcement)
+    }
+
+    /// Fetch the tools list published by a server
+    pub async fn fetch_tools(&self, server_pubkey: &PublicKey) -> Result<Vec<serde_json::Value>> {
+        self.fetch_list(server_pubkey, TOOLS_LIST_KIND, "tools").await
+    }
+
+    /// Fetch the resources list published by a server
+    pub async fn fetch_resources(&self, server_pubkey: &PublicKey) -> Result<Vec<serde_json::Value>> {
+        self.fetch_list(server_pubkey, RESOURCES_LIST_KIND, "resources").await
+    }
+
+    /// Fetch the resource templates list published by a server
+    pub async fn fetch_resource_templates(&self, server_pubkey: &PublicKey) -> Result<Vec<serde_json::Value>> {
+        self.fetch_list(server_pubkey, RESOURCETEMPLATES_LIST_KIND, "resourceTemplates").await
+    }
+
+    /// Fetch the prompts list published by a server
+    pub async fn fetch_prompts(&self, server_pubkey: &PublicKey) -> Result<Vec<serde_json::Value>> {
+        self.fetch_list(server_pubkey, PROMPTS_LIST_KIND, "prompts").await
+    }
+
+    /// Fetch the latest capability list of the given kind, empty if none was published
+    async fn fetch_list(&self, server_pubkey: &PublicKey, kind: u16, key: &str) -> Result<Vec<serde_json::Value>> {
+        let filter = Filter::new()
+            .kind(Kind::from(kind))
+            .author(*server_pubkey)
+            .limit(1);
+
+        let events = self.relay_pool.fetch_events(vec![filter], FETCH_TIMEOUT).await?;
+
+        let Some(event) = events.first() else {
+            return Ok(Vec::new());
+        };
+
+        // Retracted lists are replaced by empty events
+        if event.content.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let list: serde_json::Value = serde_json::from_str(&event.content)?;
+
+        list[key]
+            .as_array()
+            .cloned()
+            .ok_or_else(|| Error::InvalidMessage(format!("Missing `{}` array in list event", key)))
+    }
+
+    /// Requirements of a server, fetching its announcement on first contact
+    async fn requirements(&self, server_pubkey: &PublicKey) -> ServerRequirements {
+        if let Some(requirements) = self.server_requirements.read().await.get(server_pubkey) {
+            return *requirements;
+        }
+
+        if let Err(e) = self.fetch_announcement(server_pubkey).await {
+            tracing::warn!("Could not fetch announcement of {}: {}", server_pubkey.to_hex(), e);
+        }
+        self.server_requirements
+            .read()
+            .await
+            .get(server_pubkey)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Forget what a server announced, so its announcement is fetched again before
+    /// the next message; it may have raised its PoW difficulty since
+    async fn forget_requirements(&self, server_pubkey: &PublicKey) {
+        self.server_requirements.write().await.remove(server_pubkey);
+    }
+
+    /// Decide how to send messages to the given server
+    async fn delivery(&self, server_pubkey: &PublicKey) -> Result<Delivery> {
+        let requirements = self.requirements(server_pubkey).await;
+
+        if requirements.min_pow > self.config.max_pow {
+            return Err(Error::Protocol(format!(
+                "Server {} requires PoW difficulty {}, above the limit of {}",
+                server_pubkey.to_hex(),
+                requirements.min_pow,
+                self.config.max_pow
+            )));
+        }
+
+        let use_encryption = match (self.config.encryption_mode, requirements.supports_encryption) {
+            (EncryptionMode::Disabled, _) => false,
+            (EncryptionMode::Required, Some(false)) => return Err(Error::EncryptionRequired),
+            (EncryptionMode::Required, _) => true,
+            // Encrypt unless the server announced that it cannot decrypt
+            (EncryptionMode::Optional, supported) => supported.unwrap_or(true),
+        };
+
+        Ok(Delivery {
+            use_encryption,
+            pow: requirements.min_pow,
+            mode: self.config.delivery_mode,
+        })
+    }
+
+    /// Send a request to a server and wait for its response
+    ///
+    /// The request is gift wrapped according to the encryption mode and the server's
+    /// announced encryption support, and carries the proof of work the server announced.
+    /// The announcement is fetched on first contact unless [`Self::fetch_announcement`]
+    /// was called already.
     pub async fn send_request(
         &self,
         server_pubkey: &PublicKey,
         request_json: String,
-        use_encryption: bool,
     ) -> Result<String> {
-        let client = self.relay_pool.client();
+        self.send_request_with(server_pubkey, request_json, RequestOptions::default())
+            .await
+    }
 
-        let builder = EventBuilder::new(Kind::from(CTXVM_MESSAGES_KIND), request_json)
-            .tag(Tag::public_key(*server_pubkey));
+    /// Send a request with custom options and wait for its response
+    pub async fn send_request_with(
+        &self,
+        server_pubkey: &PublicKey,
+        request_json: String,
+        options: RequestOptions,
+    ) -> Result<String> {
+        self.start_request(server_pubkey, request_json, options)
+            .await?
+            .response()
+            .await
+    }
 
-        let event = client.sign_event_builder(builder).await
-            .map_err(|e| Error::Other(e.to_string()))?;
+    /// Send a JSON-RPC notification to a server; no response is expected
+    pub async fn send_notification(
+        &self,
+        server_pubkey: &PublicKey,
+        notification_json: String,
+    ) -> Result<()> {
+        let delivery = self.delivery(server_pubkey).await?;
+        let events = self.sign_messages(server_pubkey, notification_json, delivery).await?;
+        self.deliver(server_pubkey, events, delivery, EntryOwner::default()).await?;
+        metrics::global().notifications_sent.inc();
+        Ok(())
+    }
 
-        let event_id = event.id;
+    /// Send a request and return a handle to await or cancel it
+    pub async fn start_request(
+        &self,
+        server_pubkey: &PublicKey,
+        request_json: String,
+        options: RequestOptions,
+    ) -> Result<RequestHandle> {
+        self.cleanup_pending_requests().await;
 
-        // Create a channel to receive the response
-        let (tx, rx) = tokio::sync::oneshot::channel();
-        self.pending_requests.write().await.insert(event_id, tx);
+        let mut delivery = self.delivery(server_pubkey).await?;
+        if let Some(mode) = options.delivery_mode {
+            delivery.mode = mode;
+        }
+        let mut request = serde_json::from_str::<serde_json::Value>(&request_json).unwrap_or_default();
+        let request_id = request.get("id").cloned();
 
-        // Send the event
-        let _final_event_id = if use_encryption {
-            // Convert to UnsignedEvent for gift wrapping
-            let rumor = UnsignedEvent::new(
-                event.pubkey,
-                event.created_at,
-                event.kind,
-                event.tags.clone(),
-                event.content.clone(),
+        // Servers only offload payloads for clients that say they can download them
+        let request_json = if self.config.blob_store.is_some() && request["method"] == "initialize" {
+            blob::advertise(&mut request);
+            request.to_string()
+        } else {
+            request_json
+        };
+
+        let span = tracing::info_span!(
+            "cvm.request",
+            method = request["method"].as_str().unwrap_or_default(),
+            server = %server_pubkey,
+            encrypted = delivery.use_encryption,
+            event_id = field::Empty,
+            latency_ms = field::Empty,
+        );
+
+        // Latency includes signing, and mining when the server requires PoW
+        let sent_at = Instant::now();
+
+        // Large requests become several chunk events; the server replies to the last one
+        let events = self
+            .sign_messages(server_pubkey, request_json, delivery)
+            .instrument(span.clone())
+            .await?;
+        let event_id = events
+            .last()
+            .map(|event| event.id)
+            .ok_or_else(|| Error::InvalidMessage("Empty request".to_string()))?;
+        span.record("event_id", field::display(event_id));
+        let deadline = options.resolve_deadline(self.config.request_timeout);
+
+        // Register before publishing so a fast response cannot be missed
+        let (tx, rx) = oneshot::channel();
+        self.pending_requests
+            .write()
+            .await
+            .insert(
+                event_id,
+                PendingRequest {
+                    sender: tx,
+                    server_pubkey: *server_pubkey,
+                    deadline,
+                },
             );
-            let output = client.as_ref()
-                .gift_wrap(server_pubkey, rumor, Vec::<Tag>::new())
+
+        let owner = EntryOwner {
+            request: Some(event_id),
+            expires_at: Some(unix_time(deadline)),
+        };
+        if let Err(e) = self.deliver(server_pubkey, events, delivery, owner).instrument(span.clone()).await {
+            self.pending_requests.write().await.remove(&event_id);
+            return Err(e);
+        }
+        metrics::global().requests_sent.inc();
+
+        Ok(RequestHandle {
+            response: rx,
+            deadline,
+            sent_at,
+            span,
+            cancel: CancelHandle {
+                transport: self.clone(),
+                server_pubkey: *server_pubkey,
+                event_id,
+                request_id,
+                delivery,
+            },
+        })
+    }
+
+    /// Drop pending requests whose deadline passed or whose caller went away
+    pub async fn cleanup_pending_requests(&self) {
+        let now = Instant::now();
+        self.pending_requests
+            .write()
+            .await
+            .retain(|_, pending| pending.deadline > now && !pending.sender.is_closed());
+    }
+
+    /// Sign a ContextVM message addressed to a server, split into chunk events if large
+    ///
+    /// Plaintext messages are mined to the server's PoW difficulty; gift wrapped ones get
+    /// their PoW on the gift wrap in [`Self::deliver`].
+    async fn sign_messages(&self, server_pubkey: &PublicKey, content: String, delivery: Delivery) -> Result<Vec<Event>> {
+        let client = self.relay_pool.client();
+        let signer = client.signer().await.map_err(|e| Error::Other(e.to_string()))?;
+        let pubkey = signer.get_public_key().await.map_err(|e| Error::Other(e.to_string()))?;
+        let pow = if delivery.use_encryption { 0 } else { delivery.pow };
+        let mut events = Vec::new();
+
+        for (content, chunk_tag) in chunking::split_message(&content, self.config.chunk_size)? {
+            let mut builder = EventBuilder::new(Kind::from(CTXVM_MESSAGES_KIND), content)
+                .tag(Tag::public_key(*server_pubkey))
+                .tags(chunk_tag)
+                .tags(self.config.message_ttl.map(freshness::expiration_tag));
+            if pow > 0 {
+                builder = builder.pow(pow);
+            }
+
+            // Mining is CPU-bound, keep it off the async workers
+            let unsigned = tokio::task::spawn_blocking(move || builder.build(pubkey))
                 .await
-                .map_err(|e| Error::Encryption(e.to_string()))?;
-            output.val
-        } else {
-            self.relay_pool.publish(event).await?
+                .map_err(|e| Error::Other(e.to_string()))?;
+            let event = signer
+                .sign_event(unsigned)
+                .await
+                .map_err(|e| Error::Other(e.to_string()))?;
+            events.push(event);
+        }
+
+        Ok(events)
+    }
+
+    /// Publish signed messages in order, gift wrapping them if requested
+    async fn deliver(
+        &self,
+        server_pubkey: &PublicKey,
+        events: Vec<Event>,
+        delivery: Delivery,
+        owner: EntryOwner,
+    ) -> Result<()> {
+        for event in events {
+            let event = if delivery.use_encryption {
+                // Convert to UnsignedEvent for gift wrapping
+                let rumor = UnsignedEvent::new(
+                    event.pubkey,
+                    event.created_at,
+                    event.kind,
+                    event.tags.clone(),
+                    event.content.clone(),
+                );
+                self.relay_pool
+                    .gift_wrap(
+                        server_pubkey,
+                        rumor,
+                        self.config.message_ttl.map(freshness::expiration_tag),
+                        delivery.pow,
+                    )
+                    .await?
+            } else {
+                event
+            };
+            self.publish_or_queue(event, delivery.mode, owner).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Inline the blobs a response references, if a blob store is configured
+    async fn restore_blobs(&self, response_json: String) -> Result<String> {
+        let Some(store) = &self.config.blob_store else {
+            return Ok(response_json);
+        };
+        let mut response: serde_json::Value = serde_json::from_str(&response_json)?;
+
+        match blob::restore(store.as_ref(), &mut response).await? {
+            0 => Ok(response_json),
+            _ => Ok(response.to_string()),
+        }
+    }
+
+    /// Publish an event, queueing it in the outbox if no relay accepts it and the mode allows
+    async fn publish_or_queue(&self, event: Event, mode: DeliveryMode, owner: EntryOwner) -> Result<()> {
+        if mode == DeliveryMode::FailFast {
+            return self.relay_pool.publish(event).await.map(|_| ());
+        }
+
+        // Never overtake queued messages; otherwise take a place in line and publish
+        // without holding the lock
+        let sequence = {
+            let mut outbox = self.outbox.lock().await;
+            if !outbox.is_empty() {
+                return outbox.push(event, owner);
+            }
+            outbox.reserve()
+        };
+
+        match self.relay_pool.publish(event.clone()).await {
+            Ok(_) => Ok(()),
+            // Retrying will not make a relay accept us
+            Err(e @ Error::Auth(_)) => Err(e),
+            Err(e) => {
+                tracing::info!("Queueing message {}: {}", event.id, e);
+                self.outbox.lock().await.insert(sequence, event, owner)
+            }
+        }
+    }
+
+    /// Send queued messages in order whenever a relay is connected, backing off after failures
+    ///
+    /// Requests nobody waits for any more, because they timed out or were cancelled,
+    /// are dropped instead of sent.
+    async fn flush_outbox(&self) {
+        let mut interval = tokio::time::interval(OUTBOX_POLL_INTERVAL);
+        let mut retry_at = Instant::now();
+
+        loop {
+            interval.tick().await;
+            if Instant::now() < retry_at || self.outbox.lock().await.is_empty() {
+                continue;
+            }
+            let connected = self.relay_pool.status().await.iter().any(|relay| relay.connected);
+            if !connected {
+                continue;
+            }
+
+            if let Err(e) = self.outbox.lock().await.prune() {
+                tracing::warn!("Failed to prune outbox: {}", e);
+            }
+
+            loop {
+                let Some((sequence, owner, event)) = self
+                    .outbox
+                    .lock()
+                    .await
+                    .front()
+                    .map(|entry| (entry.sequence, entry.owner, entry.event.clone()))
+                else {
+                    break;
+                };
+
+                if let Some(request) = owner.request {
+                    if !self.pending_requests.read().await.contains_key(&request) {
+                        if let Err(e) = self.outbox.lock().await.discard_request(&request) {
+                            tracing::warn!("Failed to discard abandoned request from outbox: {}", e);
+                            break;
+                        }
+                        continue;
+                    }
+                }
+
+                // The entry stays queued while it is published, so nothing overtakes it
+                match self.relay_pool.publish(event).await {
+                    Ok(event_id) => {
+                        tracing::debug!("Delivered queued message {}", event_id);
+                        if let Err(e) = self.outbox.lock().await.delivered(sequence) {
+                            tracing::warn!("Failed to remove delivered message from outbox: {}", e);
+                        }
+                    }
+                    Err(e) => {
+                        let delay = self.outbox.lock().await.record_failure();
+                        tracing::warn!("Failed to deliver queued message, retrying in {:?}: {}", delay, e);
+                        retry_at = Instant::now() + delay;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Unix time of a deadline, for outbox entries that may outlive the process
+fn unix_time(deadline: Instant) -> u64 {
+    Timestamp::now().as_u64() + deadline.saturating_duration_since(Instant::now()).as_secs()
+}
+
+/// Whether a JSON-RPC message was initiated by the server (it has a `method`)
+fn is_server_message(content: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(content)
+        .map(|message| message.get("method").is_some())
+        .unwrap_or(false)
+}
+
+/// Check that a response was authored by the server the request was sent to
+///
+/// For gift wraps the seal author must also match the rumor author, since the rumor
+/// itself is unsigned and its `pubkey` could otherwise be forged.
+fn verify_response_origin(
+    expected_server: &PublicKey,
+    rumor: &UnsignedEvent,
+    seal_author: Option<&PublicKey>,
+) -> Result<()> {
+    if let Some(seal_author) = seal_author {
+        if seal_author != &rumor.pubkey {
+            return Err(Error::Protocol(format!(
+                "Seal author {} does not match rumor author {}",
+                seal_author.to_hex(),
+                rumor.pubkey.to_hex()
+            )));
+        }
+    }
+
+    if &rumor.pubkey != expected_server {
+        return Err(Error::Protocol(format!(
+            "Response from {} but request was sent to {}",
+            rumor.pubkey.to_hex(),
+            expected_server.to_hex()
+        )));
+    }
+
+    Ok(())
+}
+
+/// Handle to an in-flight request
+pub struct RequestHandle {
+    response: oneshot::Receiver<Result<UnsignedEvent>>,
+    deadline: Instant,
+    sent_at: Instant,
+    /// `cvm.request` span, closed once the response is handled
+    span: Span,
+    cancel: CancelHandle,
+}
+
+impl RequestHandle {
+    /// Event ID of the request, referenced by the server's response
+    pub fn event_id(&self) -> EventId {
+        self.cancel.event_id
+    }
+
+    /// Handle that can cancel this request from another task
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.cancel.clone()
+    }
+
+    /// Cancel the request
+    pub async fn cancel(self, reason: Option<&str>) -> Result<()> {
+        self.cancel.cancel(reason).await
+    }
+
+    /// Wait for the response content (a JSON string)
+    ///
+    /// On timeout the request is cancelled on the server as well. Blobs the response
+    /// references are downloaded and verified if the transport has a blob store.
+    pub async fn response(self) -> Result<String> {
+        let span = self.span;
+        match tokio::time::timeout_at(self.deadline, self.response).await {
+            Ok(Ok(response)) => {
+                let latency = self.sent_at.elapsed();
+                metrics::global().responses_received.inc();
+                metrics::global().client_latency.observe(latency);
+                span.record("latency_ms", latency.as_millis() as u64);
+
+                let content = response?.content;
+                self.cancel.transport.restore_blobs(content).instrument(span).await
+            }
+            // The pending entry was removed by a cancel handle; past the deadline it was
+            // evicted by the cleanup instead, which is a timeout
+            Ok(Err(_)) if Instant::now() < self.deadline => Err(Error::Cancelled),
+            Ok(Err(_)) | Err(_) => {
+                metrics::global().request_timeouts.inc();
+                span.in_scope(|| tracing::warn!("Request timed out"));
+                // A server that raised its requirements drops our messages silently
+                self.cancel
+                    .transport
+                    .forget_requirements(&self.cancel.server_pubkey)
+                    .await;
+                // The pending entry may already be evicted, but the server still runs the request
+                if let Err(e) = self
+                    .cancel
+                    .cancel_unconditionally(Some("Request timed out"))
+                    .instrument(span.clone())
+                    .await
+                {
+                    span.in_scope(|| tracing::warn!("Failed to cancel timed out request: {}", e));
+                }
+                Err(Error::Timeout)
+            }
+        }
+    }
+}
+
+/// Cancels an in-flight request, notifying the server with `notifications/cancelled`
+#[derive(Clone)]
+pub struct CancelHandle {
+    transport: NostrClientTransport,
+    server_pubkey: PublicKey,
+    event_id: EventId,
+    request_id: Option<serde_json::Value>,
+    delivery: Delivery,
+}
+
+impl CancelHandle {
+    /// Cancel the request; does nothing if it already completed
+    ///
+    /// A request still waiting in the outbox is dropped from it and never sent.
+    pub async fn cancel(&self, reason: Option<&str>) -> Result<()> {
+        let removed = self
+            .transport
+            .pending_requests
+            .write()
+            .await
+            .remove(&self.event_id)
+            .is_some();
+
+        if !removed {
+            self.transport.outbox.lock().await.discard_request(&self.event_id)?;
+            return Ok(());
+        }
+        self.notify_server(reason).await
+    }
+
+    /// Cancel the request even if it is no longer pending, as after a timeout
+    async fn cancel_unconditionally(&self, reason: Option<&str>) -> Result<()> {
+        self.transport.pending_requests.write().await.remove(&self.event_id);
+        self.notify_server(reason).await
+    }
+
+    /// Drop the request from the outbox, or tell the server it was cancelled if it left
+    async fn notify_server(&self, reason: Option<&str>) -> Result<()> {
+        // A request that never left the outbox needs no cancellation
+        let unsent = self.transport.outbox.lock().await.discard_request(&self.event_id)?;
+        if unsent > 0 {
+            return Ok(());
+        }
+
+        // Only requests carrying a JSON-RPC id can be referenced by the server
+        let Some(request_id) = self.request_id.clone() else {
+            return Ok(());
         };
 
-        // Wait for response with timeout
-        let response_event = tokio::time::timeout(Duration::from_secs(30), rx)
+        let mut params = serde_json::json!({ "requestId": request_id });
+        if let Some(reason) = reason {
+            params["reason"] = serde_json::Value::String(reason.to_string());
+        }
+        let notification = serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/cancelled",
+            "params": params,
+        });
+
+        let events = self
+            .transport
+            .sign_messages(&self.server_pubkey, notification.to_string(), self.delivery)
+            .await?;
+        self.transport
+            .deliver(&self.server_pubkey, events, self.delivery, EntryOwner::default())
             .await
-            .map_err(|_| Error::Timeout)?
-            .map_err(|_| Error::Transport("Response channel closed".to_string()))?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response_from(keys: &Keys) -> UnsignedEvent {
+        EventBuilder::new(Kind::from(CTXVM_MESSAGES_KIND), r#"{"jsonrpc":"2.0","id":1,"result":{}}"#)
+            .build(keys.public_key())
+    }
+
+    #[test]
+    fn test_verify_response_origin() {
+        let server = Keys::generate();
+        let attacker = Keys::generate();
+
+        // Genuine replies, plaintext and sealed by the server
+        let genuine = response_from(&server);
+        assert!(verify_response_origin(&server.public_key(), &genuine, None).is_ok());
+        assert!(verify_response_origin(&server.public_key(), &genuine, Some(&server.public_key())).is_ok());
+
+        // Reply authored by someone else who saw the request ID
+        let spoofed = response_from(&attacker);
+        assert!(verify_response_origin(&server.public_key(), &spoofed, None).is_err());
+        assert!(verify_response_origin(&server.public_key(), &spoofed, Some(&attacker.public_key())).is_err());
+
+        // Rumor claiming to be the server but sealed by the attacker
+        assert!(verify_response_origin(&server.public_key(), &genuine, Some(&attacker.public_key())).is_err());
+    }
 
-        // Return response content as JSON string
-        Ok(response_event.content)
+    #[test]
+    fn test_is_server_message() {
+        assert!(is_server_message(r#"{"jsonrpc":"2.0","method":"notifications/progress","params":{}}"#));
+        assert!(is_server_message(r#"{"jsonrpc":"2.0","id":3,"method":"ping"}"#));
+        assert!(!is_server_message(r#"{"jsonrpc":"2.0","id":1,"result":{}}"#));
+        assert!(!is_server_message("not json"));
     }
 }
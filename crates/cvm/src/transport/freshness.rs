@@ -0,0 +1,65 @@
+//! Message freshness: replay windows and NIP-40 expiration
+
+use nostr_sdk::prelude::*;
+use std::time::Duration;
+
+/// Tag name of NIP-40 expiration timestamps
+const EXPIRATION_TAG: &str = "expiration";
+
+/// NIP-40 expiration tag `ttl` from now
+pub(crate) fn expiration_tag(ttl: Duration) -> Tag {
+    Tag::expiration(Timestamp::from(Timestamp::now().as_u64() + ttl.as_secs()))
+}
+
+/// Whether the tags carry a NIP-40 expiration that has passed
+pub(crate) fn is_expired<'a, I>(event_tags: I) -> bool
+where
+    I: IntoIterator<Item = &'a Tag>,
+{
+    let now = Timestamp::now().as_u64();
+
+    event_tags.into_iter().any(|tag| match tag.as_slice() {
+        [name, expiration, ..] if name == EXPIRATION_TAG => expiration
+            .parse::<u64>()
+            .map(|expiration| expiration <= now)
+            .unwrap_or(false),
+        _ => false,
+    })
+}
+
+/// Whether `created_at` lies within `window` of now, in either direction
+///
+/// Older messages may be replays; messages dated far in the future would stay valid for
+/// longer than the window.
+pub(crate) fn within_window(created_at: Timestamp, window: Duration) -> bool {
+    let now = Timestamp::now().as_u64();
+    let created_at = created_at.as_u64();
+
+    now.abs_diff(created_at) <= window.as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expiration() {
+        let future = expiration_tag(Duration::from_secs(60));
+        let past = Tag::expiration(Timestamp::from(Timestamp::now().as_u64() - 60));
+        let other = Tag::public_key(Keys::generate().public_key());
+
+        assert!(!is_expired([&future, &other]));
+        assert!(is_expired([&other, &past]));
+        assert!(!is_expired(std::iter::empty()));
+    }
+
+    #[test]
+    fn test_replay_window() {
+        let window = Duration::from_secs(300);
+        let now = Timestamp::now().as_u64();
+
+        assert!(within_window(Timestamp::from(now - 10), window));
+        assert!(!within_window(Timestamp::from(now - 600), window));
+        assert!(!within_window(Timestamp::from(now + 600), window));
+    }
+}
@@ -2,6 +2,10 @@
 
 pub mod server;
 pub mod client;
+mod access;
+mod chunking;
+mod freshness;
+mod outbox;
 
 pub use server::NostrServerTransport;
 pub use client::NostrClientTransport;
@@ -0,0 +1,380 @@
+//! Outbox of signed messages waiting for a relay to accept them
+//!
+//! Entries are the final events as they would have been published: plaintext messages
+//! or gift wraps. With a directory each notification is also written to
+//! `<dir>/<sequence>-<event id>.json`, so queued notifications survive a restart and are
+//! picked up by the next client using the same directory.
+//!
+//! Entries belonging to a request carry the request's event ID and response deadline,
+//! so they can be discarded once nobody waits for the response: sending a request late
+//! would only make the server run it after the caller gave up, and perhaps retried.
+//! For the same reason requests are only kept in memory: after a restart nobody waits
+//! for their responses.
+
+use super::freshness;
+use crate::core::error::{Error, Result};
+use nostr_sdk::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// First retry delay after a failed flush, doubled on every further failure
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+
+/// Upper bound for the retry delay
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// An entry as stored on disk
+#[derive(Serialize, Deserialize)]
+struct StoredEntry {
+    /// Unix time the event was queued
+    queued_at: u64,
+    /// Unix time after which the entry is useless
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
+    /// Request event the entry belongs to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    request: Option<EventId>,
+    event: Event,
+}
+
+/// What a queued event belongs to
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct EntryOwner {
+    /// Event ID of the request, as its response references it
+    pub request: Option<EventId>,
+    /// Unix time after which sending is pointless, e.g. the response deadline
+    pub expires_at: Option<u64>,
+}
+
+pub(crate) struct Entry {
+    pub sequence: u64,
+    queued_at: u64,
+    pub owner: EntryOwner,
+    pub event: Event,
+}
+
+/// FIFO queue of events, optionally persisted to a directory
+pub(crate) struct Outbox {
+    dir: Option<PathBuf>,
+    /// Entries queued longer than this are dropped
+    max_age: Duration,
+    entries: VecDeque<Entry>,
+    next_sequence: u64,
+    /// Failed flushes in a row, for backoff
+    failures: u32,
+}
+
+impl Outbox {
+    /// Open an outbox, loading the entries persisted in `dir`
+    pub(crate) fn open(dir: Option<PathBuf>, max_age: Duration) -> Result<Self> {
+        let mut outbox = Self {
+            dir,
+            max_age,
+            entries: VecDeque::new(),
+            next_sequence: 0,
+            failures: 0,
+        };
+
+        if let Some(dir) = outbox.dir.clone() {
+            fs::create_dir_all(&dir)?;
+            outbox.load(&dir)?;
+        }
+
+        Ok(outbox)
+    }
+
+    fn load(&mut self, dir: &Path) -> Result<()> {
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("json") {
+                continue;
+            }
+            let sequence = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.split('-').next())
+                .and_then(|sequence| sequence.parse::<u64>().ok());
+            let stored = fs::read_to_string(&path)
+                .ok()
+                .and_then(|contents| serde_json::from_str::<StoredEntry>(&contents).ok());
+
+            match (sequence, stored) {
+                // Written by older versions; the caller waiting for the response is gone
+                (Some(_), Some(stored)) if stored.request.is_some() => {
+                    tracing::debug!("Dropping request {} queued by an earlier run", stored.event.id);
+                    let _ = fs::remove_file(&path);
+                }
+                (Some(sequence), Some(stored)) if stored.event.verify().is_ok() => {
+                    entries.push(Entry {
+                        sequence,
+                        queued_at: stored.queued_at,
+                        owner: EntryOwner {
+                            request: stored.request,
+                            expires_at: stored.expires_at,
+                        },
+                        event: stored.event,
+                    });
+                }
+                _ => tracing::warn!("Ignoring invalid outbox entry {}", path.display()),
+            }
+        }
+
+        entries.sort_by_key(|entry| entry.sequence);
+        self.next_sequence = entries.last().map_or(0, |entry| entry.sequence + 1);
+        self.entries = entries.into();
+
+        if !self.entries.is_empty() {
+            tracing::info!("Loaded {} queued messages from {}", self.entries.len(), dir.display());
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Queue an event behind the ones already waiting
+    pub(crate) fn push(&mut self, event: Event, owner: EntryOwner) -> Result<()> {
+        let sequence = self.reserve();
+        self.insert(sequence, event, owner)
+    }
+
+    /// Take a place in line for an event that is being published without queueing
+    ///
+    /// If publishing fails, [`Self::insert`] queues the event at this place, ahead of
+    /// anything queued meanwhile.
+    pub(crate) fn reserve(&mut self) -> u64 {
+        let sequence = self.next_sequence;
+        self.next_sequence += 1;
+        sequence
+    }
+
+    /// Queue an event at a place taken with [`Self::reserve`]
+    ///
+    /// Only notifications are persisted; request entries live in memory.
+    pub(crate) fn insert(&mut self, sequence: u64, event: Event, owner: EntryOwner) -> Result<()> {
+        let entry = Entry {
+            sequence,
+            queued_at: Timestamp::now().as_u64(),
+            owner,
+            event,
+        };
+
+        if let (Some(dir), None) = (&self.dir, owner.request) {
+            let stored = StoredEntry {
+                queued_at: entry.queued_at,
+                expires_at: owner.expires_at,
+                request: owner.request,
+                event: entry.event.clone(),
+            };
+            let json = serde_json::to_string(&stored)?;
+
+            // Write then rename, so a crash never leaves a truncated entry behind
+            let path = entry_path(dir, &entry);
+            let tmp = path.with_extension("tmp");
+            fs::write(&tmp, json)?;
+            fs::rename(&tmp, &path)?;
+        }
+
+        let position = self.entries.partition_point(|queued| queued.sequence < sequence);
+        self.entries.insert(position, entry);
+        Ok(())
+    }
+
+    /// Oldest queued entry
+    pub(crate) fn front(&self) -> Option<&Entry> {
+        self.entries.front()
+    }
+
+    /// Remove an entry once a relay accepted it
+    pub(crate) fn delivered(&mut self, sequence: u64) -> Result<()> {
+        self.failures = 0;
+        match self.entries.iter().position(|entry| entry.sequence == sequence) {
+            Some(position) => {
+                let entry = self.entries.remove(position);
+                entry.map_or(Ok(()), |entry| self.remove_file(&entry))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Drop the entries of a request, returning how many were dropped
+    pub(crate) fn discard_request(&mut self, request: &EventId) -> Result<usize> {
+        self.discard_where(|owner| owner.request.as_ref() == Some(request))
+    }
+
+    /// Drop the entries of all requests, keeping notifications
+    pub(crate) fn discard_requests(&mut self) -> Result<usize> {
+        self.discard_where(|owner| owner.request.is_some())
+    }
+
+    fn discard_where<F>(&mut self, discard: F) -> Result<usize>
+    where
+        F: Fn(&EntryOwner) -> bool,
+    {
+        let (discarded, kept): (Vec<Entry>, Vec<Entry>) = std::mem::take(&mut self.entries)
+            .into_iter()
+            .partition(|entry| discard(&entry.owner));
+        self.entries = kept.into();
+
+        for entry in &discarded {
+            tracing::debug!("Discarding queued message {}: its request was abandoned", entry.event.id);
+            self.remove_file(entry)?;
+        }
+
+        Ok(discarded.len())
+    }
+
+    /// Record a failed flush and return how long to wait before the next one
+    pub(crate) fn record_failure(&mut self) -> Duration {
+        self.failures += 1;
+        let exponent = (self.failures - 1).min(16);
+        RETRY_BASE_DELAY.saturating_mul(1 << exponent).min(RETRY_MAX_DELAY)
+    }
+
+    /// Drop entries that are too old or past their deadline, returning how many were dropped
+    pub(crate) fn prune(&mut self) -> Result<usize> {
+        let now = Timestamp::now().as_u64();
+        let max_age = self.max_age.as_secs();
+
+        let (stale, fresh): (Vec<Entry>, Vec<Entry>) = std::mem::take(&mut self.entries)
+            .into_iter()
+            .partition(|entry| {
+                now.saturating_sub(entry.queued_at) > max_age
+                    || entry.owner.expires_at.is_some_and(|expires_at| expires_at < now)
+                    || freshness::is_expired(entry.event.tags.iter())
+            });
+        self.entries = fresh.into();
+
+        for entry in &stale {
+            tracing::warn!("Dropping queued message {}: not delivered in time", entry.event.id);
+            self.remove_file(entry)?;
+        }
+
+        Ok(stale.len())
+    }
+
+    fn remove_file(&self, entry: &Entry) -> Result<()> {
+        let Some(dir) = &self.dir else {
+            return Ok(());
+        };
+
+        match fs::remove_file(entry_path(dir, entry)) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(Error::from(e)),
+        }
+    }
+}
+
+fn entry_path(dir: &Path, entry: &Entry) -> PathBuf {
+    dir.join(format!("{:012}-{}.json", entry.sequence, entry.event.id.to_hex()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(keys: &Keys, content: &str) -> Event {
+        EventBuilder::text_note(content).sign_with_keys(keys).unwrap()
+    }
+
+    #[test]
+    fn test_persisted_in_order() {
+        let dir = std::env::temp_dir().join(format!("cvm-outbox-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let keys = Keys::generate();
+        let max_age = Duration::from_secs(60);
+
+        let mut outbox = Outbox::open(Some(dir.clone()), max_age).unwrap();
+        let first = message(&keys, "first");
+        let second = message(&keys, "second");
+        outbox.push(first.clone(), EntryOwner::default()).unwrap();
+        outbox.push(second.clone(), EntryOwner::default()).unwrap();
+        let request = message(&keys, "request");
+        let owner = EntryOwner {
+            request: Some(request.id),
+            expires_at: None,
+        };
+        outbox.push(request, owner).unwrap();
+        assert_eq!(outbox.len(), 3);
+
+        // A new client picks up where the last one stopped, without the request
+        let mut reopened = Outbox::open(Some(dir.clone()), max_age).unwrap();
+        assert_eq!(reopened.len(), 2);
+        let front = reopened.front().unwrap();
+        assert_eq!(front.event, first);
+        let sequence = front.sequence;
+        reopened.delivered(sequence).unwrap();
+        reopened.push(message(&keys, "third"), EntryOwner::default()).unwrap();
+
+        let mut reopened = Outbox::open(Some(dir.clone()), max_age).unwrap();
+        assert_eq!(reopened.front().map(|entry| &entry.event), Some(&second));
+        assert_eq!(reopened.len(), 2);
+
+        reopened.max_age = Duration::ZERO;
+        for entry in reopened.entries.iter_mut() {
+            entry.queued_at -= 1;
+        }
+        assert_eq!(reopened.prune().unwrap(), 2);
+        assert!(Outbox::open(Some(dir.clone()), max_age).unwrap().is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        let mut outbox = Outbox::open(None, Duration::from_secs(60)).unwrap();
+        assert_eq!(outbox.record_failure(), RETRY_BASE_DELAY);
+        assert_eq!(outbox.record_failure(), RETRY_BASE_DELAY * 2);
+        for _ in 0..20 {
+            outbox.record_failure();
+        }
+        assert_eq!(outbox.record_failure(), RETRY_MAX_DELAY);
+
+        outbox.push(message(&Keys::generate(), "sent"), EntryOwner::default()).unwrap();
+        let sequence = outbox.front().unwrap().sequence;
+        outbox.delivered(sequence).unwrap();
+        assert_eq!(outbox.record_failure(), RETRY_BASE_DELAY);
+    }
+
+    #[test]
+    fn test_requests_are_discarded_and_reserved_places_kept() {
+        let keys = Keys::generate();
+        let mut outbox = Outbox::open(None, Duration::from_secs(60)).unwrap();
+        let request = message(&keys, "request");
+        let owner = EntryOwner {
+            request: Some(request.id),
+            expires_at: None,
+        };
+
+        // A message that failed to publish keeps its place ahead of later ones
+        let reserved = outbox.reserve();
+        outbox.push(request.clone(), owner).unwrap();
+        let notification = message(&keys, "notification");
+        outbox.insert(reserved, notification.clone(), EntryOwner::default()).unwrap();
+        assert_eq!(outbox.front().map(|entry| &entry.event), Some(&notification));
+
+        assert_eq!(outbox.discard_request(&request.id).unwrap(), 1);
+        assert_eq!(outbox.len(), 1);
+
+        // Requests past their deadline are pruned
+        let late = EntryOwner {
+            request: Some(request.id),
+            expires_at: Some(Timestamp::now().as_u64() - 1),
+        };
+        outbox.push(request, late).unwrap();
+        assert_eq!(outbox.prune().unwrap(), 1);
+        assert_eq!(outbox.discard_requests().unwrap(), 0);
+        assert_eq!(outbox.len(), 1);
+    }
+}
@@ -3,12 +3,22 @@
 use crate::core::{
     constants::*, error::{Error, Result}, types::*,
 };
-use crate::relay::RelayPool;
+use crate::blob::{self, BlobStore};
+use crate::core::cache::BoundedSet;
+use crate::core::constants::tags;
+use crate::metrics::{self, DropReason};
+use crate::relay::{RelayHealth, RelayPool, Subscription};
+use super::access::{RateLimiter, RefusalReplies, Refusal};
+use super::chunking::{self, Chunk, ChunkAssembler};
+use super::freshness;
 use nostr_sdk::prelude::*;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::sync::RwLock;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, Mutex, RwLock};
+use tokio::task::JoinHandle;
+use tracing::{field, Instrument};
 
 /// Incoming message metadata
 #[derive(Debug, Clone)]
@@ -22,27 +32,103 @@ pub struct IncomingMessage {
 /// Server-side transport configuration
 pub struct NostrServerTransportConfig {
     pub relay_urls: Vec<String>,
+    /// Relays among `relay_urls` that require NIP-42 authentication
+    pub auth_relay_urls: Vec<String>,
     pub encryption_mode: EncryptionMode,
     pub server_info: Option<ServerInfo>,
+    /// MCP server capabilities advertised in announcements and `initialize` results
+    pub capabilities: serde_json::Value,
     pub session_timeout: Duration,
+    /// Messages larger than this many bytes are sent as chunk events
+    pub chunk_size: usize,
+    /// Ignore messages created longer ago (or further ahead) than this; `None` accepts any age
+    pub max_message_age: Option<Duration>,
+    /// Number of recently seen event IDs remembered to drop duplicates
+    pub seen_cache_size: usize,
+    /// Add a NIP-40 expiration this far in the future to outgoing messages
+    pub message_ttl: Option<Duration>,
+    /// Serve only these clients; `None` serves every client that is not denied
+    pub allowed_clients: Option<HashSet<PublicKey>>,
+    /// Clients that are never served
+    pub denied_clients: HashSet<PublicKey>,
+    /// Limit on the messages each client may send
+    pub rate_limit: Option<RateLimit>,
+    /// Maximum number of concurrent client sessions
+    pub max_sessions: Option<usize>,
+    /// Minimum NIP-13 difficulty of inbound events (the gift wrap, when encrypted)
+    pub min_pow: Option<u8>,
+    /// Re-announce the server and its lists this often while started, with an expiration
+    /// two intervals ahead; `None`, the default, publishes them once without one
+    pub announce_interval: Option<Duration>,
+    /// Upload binary payloads of responses here instead of sending them inline, for
+    /// clients that advertise the `contextvm/blob` capability
+    pub blob_store: Option<Arc<dyn BlobStore>>,
+    /// Base64 payloads longer than this many bytes are offloaded to the blob store
+    pub blob_threshold: usize,
 }
 
 impl Default for NostrServerTransportConfig {
     fn default() -> Self {
         Self {
             relay_urls: vec!["wss://relay.damus.io".to_string()],
+            auth_relay_urls: Vec::new(),
             encryption_mode: EncryptionMode::Optional,
             server_info: None,
+            capabilities: serde_json::json!({}),
             session_timeout: Duration::from_secs(300),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            max_message_age: Some(Duration::from_secs(300)),
+            seen_cache_size: DEFAULT_SEEN_CACHE_SIZE,
+            message_ttl: None,
+            allowed_clients: None,
+            denied_clients: HashSet::new(),
+            rate_limit: None,
+            max_sessions: None,
+            min_pow: None,
+            announce_interval: None,
+            blob_store: None,
+            blob_threshold: DEFAULT_BLOB_THRESHOLD,
         }
     }
 }
 
+/// Capacity of the incoming message channel returned by [`NostrServerTransport::start`]
+const INCOMING_CHANNEL_CAPACITY: usize = 100;
+
+/// Kinds of the announcement and capability lists a server may publish
+const ANNOUNCEMENT_KINDS: [u16; 5] = [
+    SERVER_ANNOUNCEMENT_KIND,
+    TOOLS_LIST_KIND,
+    RESOURCES_LIST_KIND,
+    RESOURCETEMPLATES_LIST_KIND,
+    PROMPTS_LIST_KIND,
+];
+
 /// Server-side Nostr transport
+///
+/// Cloning is cheap and yields a handle to the same relay pool and sessions.
+#[derive(Clone)]
 pub struct NostrServerTransport {
     relay_pool: Arc<RelayPool>,
-    config: NostrServerTransportConfig,
+    config: Arc<NostrServerTransportConfig>,
     sessions: Arc<RwLock<HashMap<String, ClientSession>>>,
+    /// Last published content of each capability list, keyed by event kind
+    published_lists: Arc<RwLock<HashMap<u16, serde_json::Value>>>,
+    /// The announcement was published and has not been retracted
+    announced: Arc<AtomicBool>,
+    /// Task republishing the announcement every `announce_interval`
+    heartbeat: Arc<std::sync::Mutex<Option<JoinHandle<()>>>>,
+    /// Partially received chunked requests
+    chunks: Arc<Mutex<ChunkAssembler>>,
+    /// `initialize` result supplied by a bridged MCP server, replacing the generated one
+    initialize_override: Arc<std::sync::RwLock<Option<serde_json::Value>>>,
+    /// Recently handled event and rumor IDs, to drop copies from other relays
+    seen: Arc<Mutex<BoundedSet<EventId>>>,
+    rate_limiter: Option<Arc<Mutex<RateLimiter>>>,
+    /// Refused clients answered recently
+    refusal_replies: Arc<Mutex<RefusalReplies>>,
+    /// When each request being handled arrived, keyed by the event ID replies reference
+    in_flight: Arc<Mutex<HashMap<EventId, Instant>>>,
 }
 
 impl NostrServerTransport {
@@ -55,74 +141,278 @@ impl NostrServerTransport {
         T: IntoNostrSigner,
     {
         let relay_pool = Arc::new(RelayPool::new(signer).await?);
+        relay_pool.require_auth(&config.auth_relay_urls).await?;
+        let seen = BoundedSet::new(config.seen_cache_size);
+        let rate_limiter = config
+            .rate_limit
+            .map(|limit| Arc::new(Mutex::new(RateLimiter::new(limit))));
 
         Ok(Self {
             relay_pool,
-            config,
+            config: Arc::new(config),
             sessions: Arc::new(RwLock::new(HashMap::new())),
+            published_lists: Arc::new(RwLock::new(HashMap::new())),
+            announced: Arc::new(AtomicBool::new(false)),
+            heartbeat: Arc::new(std::sync::Mutex::new(None)),
+            chunks: Arc::new(Mutex::new(ChunkAssembler::default())),
+            initialize_override: Arc::new(std::sync::RwLock::new(None)),
+            seen: Arc::new(Mutex::new(seen)),
+            rate_limiter,
+            refusal_replies: Arc::new(Mutex::new(RefusalReplies::default())),
+            in_flight: Arc::new(Mutex::new(HashMap::new())),
         })
     }
 
+    /// Use the `initialize` result of a bridged MCP server for announcements
+    pub fn set_initialize_result(&self, result: serde_json::Value) {
+        if let Ok(mut initialize_override) = self.initialize_override.write() {
+            *initialize_override = Some(result);
+        }
+    }
+
+    /// Connection health of each relay
+    pub async fn relay_status(&self) -> Vec<RelayHealth> {
+        self.relay_pool.status().await
+    }
+
+    /// MCP `initialize` result describing this server
+    ///
+    /// This is the result set via [`Self::set_initialize_result`] if any, otherwise one
+    /// generated from the configured server info and capabilities.
+    pub fn initialize_result(&self) -> serde_json::Value {
+        let initialize_override = self
+            .initialize_override
+            .read()
+            .ok()
+            .and_then(|result| result.clone());
+        if let Some(result) = initialize_override {
+            return result;
+        }
+
+        let server_info = self.config.server_info.clone().unwrap_or_default();
+
+        let mut result = serde_json::json!({
+            "protocolVersion": MCP_PROTOCOL_VERSION,
+            "capabilities": self.config.capabilities,
+            "serverInfo": {
+                "name": server_info.name,
+                "version": server_info.version,
+            },
+        });
+        if let Some(about) = server_info.about {
+            result["instructions"] = serde_json::Value::String(about);
+        }
+
+        result
+    }
+
     /// Announce server to the relay
+    ///
+    /// Publishes a kind 11316 event whose content is the MCP `initialize` result, tagged
+    /// with the server metadata and encryption support. With an announce interval the
+    /// announcement is refreshed while the transport is started and expires once the
+    /// server misses [`MISSED_ANNOUNCEMENTS_OFFLINE`] refreshes.
     pub async fn announce(&self) -> Result<()> {
         // Connect to relays first if not already connected
         self.relay_pool.connect(&self.config.relay_urls).await?;
 
         let client = self.relay_pool.client();
 
-        // Build server announcement
-        let server_info = self.config.server_info.as_ref().ok_or_else(|| {
-            Error::Other("Server info not configured for announcement".to_string())
-        })?;
+        let initialize_result = self.initialize_result();
 
-        let announcement = serde_json::json!({
-            "name": server_info.name,
-            "version": server_info.version,
-            "about": server_info.about,
-        });
+        // Build server announcement, falling back to the bridged server's `serverInfo`
+        let server_info = self
+            .config
+            .server_info
+            .clone()
+            .or_else(|| serde_json::from_value(initialize_result["serverInfo"].clone()).ok())
+            .ok_or_else(|| {
+                Error::Other("Server info not configured for announcement".to_string())
+            })?;
 
-        let announcement_json = serde_json::to_string(&announcement)
+        let announcement_json = serde_json::to_string(&initialize_result)
             .map_err(|e| Error::Other(format!("Failed to serialize announcement: {}", e)))?;
 
+        let mut announcement_tags: Vec<Tag> = [
+            (tags::NAME, &server_info.name),
+            (tags::ABOUT, &server_info.about),
+            (tags::PICTURE, &server_info.picture),
+            (tags::WEBSITE, &server_info.website),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| {
+            value
+                .as_ref()
+                .map(|value| Tag::custom(TagKind::custom(name), [value.clone()]))
+        })
+        .collect();
+
+        if self.config.encryption_mode != EncryptionMode::Disabled {
+            announcement_tags.push(Tag::custom(
+                TagKind::custom(tags::SUPPORT_ENCRYPTION),
+                Vec::<String>::new(),
+            ));
+        }
+
+        if let Some(min_pow) = self.config.min_pow {
+            announcement_tags.push(Tag::custom(
+                TagKind::custom(tags::MIN_POW),
+                [min_pow.to_string()],
+            ));
+        }
+
+        if let Some(interval) = self.config.announce_interval {
+            announcement_tags.push(Tag::custom(
+                TagKind::custom(tags::ANNOUNCE_INTERVAL),
+                [interval.as_secs().to_string()],
+            ));
+        }
+        announcement_tags.extend(self.announcement_expiration());
+
         // Publish as kind 11316 (server announcement)
-        let builder = EventBuilder::new(Kind::from(SERVER_ANNOUNCEMENT_KIND), announcement_json);
+        let builder = EventBuilder::new(Kind::from(SERVER_ANNOUNCEMENT_KIND), announcement_json)
+            .tags(announcement_tags);
 
         let output = client.send_event_builder(builder).await
             .map_err(|e| Error::Transport(e.to_string()))?;
 
         tracing::info!("Published server announcement: {}", output.val);
+        self.announced.store(true, Ordering::SeqCst);
+
+        Ok(())
+    }
+
+    /// NIP-40 expiration of announcements, so relays drop those of crashed servers
+    fn announcement_expiration(&self) -> Option<Tag> {
+        self.config
+            .announce_interval
+            .map(|interval| freshness::expiration_tag(interval * MISSED_ANNOUNCEMENTS_OFFLINE as u32))
+    }
+
+    /// Republish the announcement and capability lists so they stay fresh
+    async fn refresh_announcements(&self) -> Result<()> {
+        if self.announced.load(Ordering::SeqCst) {
+            self.announce().await?;
+        }
+
+        let lists: Vec<(u16, serde_json::Value)> = self
+            .published_lists
+            .read()
+            .await
+            .iter()
+            .map(|(kind, list)| (*kind, list.clone()))
+            .collect();
+        for (kind, list) in lists {
+            self.send_list(kind, &list).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Withdraw the announcement and capability lists, e.g. on shutdown
+    ///
+    /// Stops the announcement heartbeat, replaces the announcement and lists with empty
+    /// events, which clients ignore, and requests their NIP-09 deletion from relays that
+    /// honour it. Covers every announcement kind, so this also cleans up after a previous
+    /// run that crashed.
+    pub async fn retract(&self) -> Result<()> {
+        self.stop_heartbeat();
+        self.relay_pool.connect(&self.config.relay_urls).await?;
+
+        self.announced.store(false, Ordering::SeqCst);
+        self.published_lists.write().await.clear();
+
+        let client = self.relay_pool.client();
+        let pubkey = client.signer().await.map_err(|e| Error::Other(e.to_string()))?
+            .get_public_key().await.map_err(|e| Error::Other(e.to_string()))?;
+
+        let mut deletion = EventDeletionRequest::new().reason("server shut down");
+        for kind in ANNOUNCEMENT_KINDS {
+            client
+                .send_event_builder(EventBuilder::new(Kind::from(kind), ""))
+                .await
+                .map_err(|e| Error::Transport(e.to_string()))?;
+            deletion = deletion.coordinate(Coordinate::new(Kind::from(kind), pubkey));
+        }
+
+        let output = client.send_event_builder(EventBuilder::delete(deletion)).await
+            .map_err(|e| Error::Transport(e.to_string()))?;
+
+        tracing::info!("Retracted server announcement: {}", output.val);
 
         Ok(())
     }
 
+    fn stop_heartbeat(&self) {
+        if let Ok(mut heartbeat) = self.heartbeat.lock() {
+            if let Some(task) = heartbeat.take() {
+                task.abort();
+            }
+        }
+    }
+
     /// Publish tools list to the relay
+    ///
+    /// Safe to call whenever the list may have changed: unchanged lists are not republished.
     pub async fn publish_tools(&self, tools: Vec<serde_json::Value>) -> Result<()> {
+        self.publish_list(TOOLS_LIST_KIND, "tools", tools).await
+    }
+
+    /// Publish resources list to the relay
+    pub async fn publish_resources(&self, resources: Vec<serde_json::Value>) -> Result<()> {
+        self.publish_list(RESOURCES_LIST_KIND, "resources", resources).await
+    }
+
+    /// Publish resource templates list to the relay
+    pub async fn publish_resource_templates(&self, templates: Vec<serde_json::Value>) -> Result<()> {
+        self.publish_list(RESOURCETEMPLATES_LIST_KIND, "resourceTemplates", templates).await
+    }
+
+    /// Publish prompts list to the relay
+    pub async fn publish_prompts(&self, prompts: Vec<serde_json::Value>) -> Result<()> {
+        self.publish_list(PROMPTS_LIST_KIND, "prompts", prompts).await
+    }
+
+    /// Publish a capability list in MCP `*/list` result format, skipping unchanged lists
+    async fn publish_list(&self, kind: u16, key: &str, items: Vec<serde_json::Value>) -> Result<()> {
+        let count = items.len();
+        let list = serde_json::json!({ key: items });
+
+        if self.published_lists.read().await.get(&kind) == Some(&list) {
+            tracing::debug!("{} list unchanged, not republishing", key);
+            return Ok(());
+        }
+
+        let event_id = self.send_list(kind, &list).await?;
+        tracing::info!("Published {} list ({} entries): {}", key, count, event_id);
+
+        self.published_lists.write().await.insert(kind, list);
+
+        Ok(())
+    }
+
+    async fn send_list(&self, kind: u16, list: &serde_json::Value) -> Result<EventId> {
         // Connect to relays first if not already connected
         self.relay_pool.connect(&self.config.relay_urls).await?;
 
         let client = self.relay_pool.client();
 
-        // Build tools list in MCP format
-        let tools_list = serde_json::json!({
-            "tools": tools
-        });
-
-        let tools_json = serde_json::to_string(&tools_list)
-            .map_err(|e| Error::Other(format!("Failed to serialize tools: {}", e)))?;
+        let list_json = serde_json::to_string(list)
+            .map_err(|e| Error::Other(format!("Failed to serialize list: {}", e)))?;
 
-        // Publish as kind 11317 (tools list)
-        let builder = EventBuilder::new(Kind::from(TOOLS_LIST_KIND), tools_json);
+        let builder = EventBuilder::new(Kind::from(kind), list_json)
+            .tags(self.announcement_expiration());
 
         let output = client.send_event_builder(builder).await
             .map_err(|e| Error::Transport(e.to_string()))?;
 
-        tracing::info!("Published tools list ({} tools): {}", tools.len(), output.val);
-
-        Ok(())
+        Ok(output.val)
     }
 
     /// Start listening for incoming MCP requests
-    pub async fn start(&self) -> Result<()> {
+    ///
+    /// Returns a channel of decoded messages. Listening stops once the receiver is dropped.
+    pub async fn start(&self) -> Result<mpsc::Receiver<IncomingMessage>> {
         // Connect to relays
         self.relay_pool.connect(&self.config.relay_urls).await?;
 
@@ -130,49 +420,143 @@ impl NostrServerTransport {
         let pubkey = client.signer().await.map_err(|e| Error::Other(e.to_string()))?
             .get_public_key().await.map_err(|e| Error::Other(e.to_string()))?;
 
-        // Subscribe to messages targeting this server (both regular and encrypted)
-        let filter = Filter::new()
-            .kinds(vec![
+        // Subscribe to messages targeting this server in the formats the encryption mode allows
+        let kinds = match self.config.encryption_mode {
+            EncryptionMode::Optional => vec![
                 Kind::from(CTXVM_MESSAGES_KIND),
                 Kind::from(GIFT_WRAP_KIND),
-            ])
-            .pubkey(pubkey);
+            ],
+            EncryptionMode::Required => vec![Kind::from(GIFT_WRAP_KIND)],
+            EncryptionMode::Disabled => vec![Kind::from(CTXVM_MESSAGES_KIND)],
+        };
+        let filter = Filter::new().kinds(kinds).pubkey(pubkey);
 
         tracing::info!("Server listening on pubkey: {}", pubkey.to_hex());
 
-        // Subscribe
-        client
-            .subscribe(filter, None)
-            .await
-            .map_err(|e| Error::Transport(e.to_string()))?;
+        let subscription = self.relay_pool.subscribe(vec![filter]).await?;
 
-        // Handle events in a loop
-        self.handle_subscription().await
-    }
+        // Handle events in the background
+        let (tx, rx) = mpsc::channel(INCOMING_CHANNEL_CAPACITY);
+        let transport = self.clone();
 
-    async fn handle_subscription(&self) -> Result<()> {
-        let client = self.relay_pool.client();
-        let mut notifications = client.notifications();
+        let reaper = self.clone();
+        let reaper_tx = tx.clone();
+
+        let heartbeat = self.clone();
+        let heartbeat_tx = tx.clone();
+
+        tokio::spawn(async move {
+            transport.handle_subscription(subscription, tx).await;
+        });
 
-        while let Ok(notification) = notifications.recv().await {
-            if let RelayPoolNotification::Event { event, .. } = notification {
-                if let Err(e) = self.handle_event(*event).await {
-                    tracing::error!("Error handling event: {}", e);
+        // Drop idle sessions until the incoming receiver goes away
+        tokio::spawn(async move {
+            let period = (reaper.config.session_timeout / 2).max(Duration::from_secs(1));
+            let mut interval = tokio::time::interval(period);
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => reaper.cleanup_inactive_sessions().await,
+                    _ = reaper_tx.closed() => break,
+                }
+            }
+        });
+
+        // Keep the announcement fresh until it is retracted or the incoming receiver goes away
+        if let Some(period) = self.config.announce_interval {
+            let task = tokio::spawn(async move {
+                let period = period.max(Duration::from_secs(1));
+                let mut interval =
+                    tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+
+                loop {
+                    tokio::select! {
+                        _ = interval.tick() => {
+                            if let Err(e) = heartbeat.refresh_announcements().await {
+                                tracing::warn!("Failed to refresh announcement: {}", e);
+                            }
+                        }
+                        _ = heartbeat_tx.closed() => break,
+                    }
+                }
+            });
+
+            if let Ok(mut heartbeat) = self.heartbeat.lock() {
+                if let Some(previous) = heartbeat.replace(task) {
+                    previous.abort();
                 }
             }
         }
 
-        Ok(())
+        Ok(rx)
+    }
+
+    async fn handle_subscription(&self, mut subscription: Subscription, tx: mpsc::Sender<IncomingMessage>) {
+        while let Some(event) = subscription.recv().await {
+            let span = tracing::info_span!(
+                "cvm.message",
+                event_id = %event.id,
+                peer = field::Empty,
+                encrypted = field::Empty,
+                method = field::Empty,
+            );
+            match self.handle_event(event).instrument(span.clone()).await {
+                Ok(Some(message)) => {
+                    if tx.send(message).await.is_err() {
+                        tracing::debug!("Incoming message receiver dropped, stopping server loop");
+                        break;
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => span.in_scope(|| tracing::error!("Error handling event: {}", e)),
+            }
+        }
     }
 
-    async fn handle_event(&self, event: Event) -> Result<()> {
+    async fn handle_event(&self, event: Event) -> Result<Option<IncomingMessage>> {
+        // The same event arrives once per relay
+        if !self.seen.lock().await.insert(event.id) {
+            tracing::debug!("Ignoring duplicate event {}", event.id);
+            metrics::global().record_drop(DropReason::Duplicate);
+            return Ok(None);
+        }
+
+        // Checked before decrypting: PoW is the cheap filter in front of everything else
+        if let Some(min_pow) = self.config.min_pow {
+            if !event.id.check_pow(min_pow) {
+                tracing::debug!("Ignoring event {} below the required PoW of {}", event.id, min_pow);
+                metrics::global().record_drop(DropReason::InsufficientPow);
+                return Ok(None);
+            }
+        }
+
+        if freshness::is_expired(event.tags.iter()) {
+            tracing::debug!("Ignoring expired event {}", event.id);
+            metrics::global().record_drop(DropReason::Expired);
+            return Ok(None);
+        }
+
         // Check if it's a gift-wrapped event
-        let (actual_event, is_encrypted) = if event.kind == Kind::from(GIFT_WRAP_KIND) {
+        let (mut actual_event, is_encrypted) = if event.kind == Kind::from(GIFT_WRAP_KIND) {
             let client = self.relay_pool.client();
             let unwrapped = client
                 .unwrap_gift_wrap(&event)
                 .await
-                .map_err(|e| Error::Decryption(e.to_string()))?;
+                .map_err(|e| {
+                    metrics::global().decrypt_failures.inc();
+                    Error::Decryption(e.to_string())
+                })?;
+
+            // The rumor is unsigned: only trust its author if the seal was signed by it
+            if unwrapped.sender != unwrapped.rumor.pubkey {
+                tracing::warn!(
+                    "Ignoring gift wrap whose seal author {} does not match rumor author {}",
+                    unwrapped.sender.to_hex(),
+                    unwrapped.rumor.pubkey.to_hex()
+                );
+                metrics::global().record_drop(DropReason::Forged);
+                return Ok(None);
+            }
             (unwrapped.rumor, true)
         } else {
             // Convert Event to UnsignedEvent for consistency
@@ -186,39 +570,339 @@ impl NostrServerTransport {
             (rumor, false)
         };
 
-        // Get or create session
+        let span = tracing::Span::current();
+        span.record("peer", field::display(actual_event.pubkey));
+        span.record("encrypted", is_encrypted);
+
+        // Refuse as soon as the sender is known, before buffering chunks; every event,
+        // chunk or not, takes a token from the rate limit
+        if let Some(refusal) = self.check_access(&actual_event.pubkey).await {
+            metrics::global().record_drop(DropReason::Refused);
+            let event_id = actual_event.id();
+            self.refuse(&actual_event.pubkey, &actual_event.content, &event_id, is_encrypted, refusal)
+                .await?;
+            return Ok(None);
+        }
+
+        // Only messages addressed to our kind are MCP traffic
+        if actual_event.kind != Kind::from(CTXVM_MESSAGES_KIND) {
+            tracing::debug!("Ignoring event of kind {}", actual_event.kind.as_u16());
+            metrics::global().record_drop(DropReason::Invalid);
+            return Ok(None);
+        }
+
+        // A rumor can be re-wrapped into new gift wraps, so de-duplicate it as well
+        if is_encrypted {
+            let rumor_id = actual_event.id();
+            if !self.seen.lock().await.insert(rumor_id) {
+                tracing::debug!("Ignoring duplicate rumor {}", rumor_id);
+                metrics::global().record_drop(DropReason::Duplicate);
+                return Ok(None);
+            }
+        }
+
+        // Gift wraps carry randomized timestamps; the rumor holds the real creation time
+        if let Some(max_age) = self.config.max_message_age {
+            if !freshness::within_window(actual_event.created_at, max_age) {
+                tracing::warn!(
+                    "Ignoring message from {} created at {}: outside the replay window",
+                    actual_event.pubkey.to_hex(),
+                    actual_event.created_at
+                );
+                metrics::global().record_drop(DropReason::Replay);
+                return Ok(None);
+            }
+        }
+
+        if freshness::is_expired(actual_event.tags.iter()) {
+            tracing::debug!("Ignoring expired message from {}", actual_event.pubkey.to_hex());
+            metrics::global().record_drop(DropReason::Expired);
+            return Ok(None);
+        }
+
+        if !self.config.encryption_mode.accepts(is_encrypted) {
+            tracing::warn!(
+                "Ignoring {} message from {} (encryption mode: {:?})",
+                if is_encrypted { "encrypted" } else { "plaintext" },
+                actual_event.pubkey.to_hex(),
+                self.config.encryption_mode
+            );
+            metrics::global().record_drop(DropReason::EncryptionMode);
+            return Ok(None);
+        }
+
+        // Responses reference the event the client signed: the rumor for gift wraps
+        let mut event_id = actual_event.id.unwrap_or(event.id);
+        let mut content = actual_event.content;
+
+        // Reassemble chunked messages; replies reference the final chunk
+        let assembled = match Chunk::from_tags(actual_event.tags.iter()) {
+            Some(Ok(chunk)) => self.chunks.lock().await.add(actual_event.pubkey, event_id, chunk, content),
+            Some(Err(e)) => Err(e),
+            None => chunking::check_size(content.len()).map(|()| Some((content, event_id))),
+        };
+        match assembled {
+            Ok(Some((message, last_event_id))) => {
+                content = message;
+                event_id = last_event_id;
+            }
+            Ok(None) => return Ok(None),
+            Err(e) => {
+                metrics::global().record_drop(DropReason::Invalid);
+                return Err(e);
+            }
+        }
+
+        // Get or create session, remembering the client's latest encryption choice
         let client_pubkey = actual_event.pubkey.to_hex();
         let mut sessions = self.sessions.write().await;
+
+        if let Some(max_sessions) = self.config.max_sessions {
+            if !sessions.contains_key(&client_pubkey) && sessions.len() >= max_sessions {
+                // Make room from idle sessions before turning the client away
+                let timeout = self.config.session_timeout;
+                sessions.retain(|_, session| session.last_activity.elapsed() < timeout);
+
+                if sessions.len() >= max_sessions {
+                    drop(sessions);
+                    metrics::global().record_drop(DropReason::Refused);
+                    self.refuse(&actual_event.pubkey, &content, &event_id, is_encrypted, Refusal::TooManySessions)
+                        .await?;
+                    return Ok(None);
+                }
+            }
+        }
+
         let session = sessions
             .entry(client_pubkey.clone())
             .or_insert_with(|| ClientSession::new(client_pubkey, is_encrypted));
 
+        session.is_encrypted = is_encrypted;
         session.update_activity();
 
-        // Log received message (actual processing should be done by higher-level code)
-        tracing::debug!("Received message from {}: {}", actual_event.pubkey.to_hex(), actual_event.content);
+        // Follow the MCP lifecycle: only `initialize` and `ping` may precede initialization
+        let message: serde_json::Value = serde_json::from_str(&content).unwrap_or_default();
+        let request_id = message.get("id").cloned();
+        if let Some(method) = message["method"].as_str() {
+            span.record("method", method);
+        }
+        let rejected = match message["method"].as_str() {
+            Some("initialize") => {
+                session.record_initialize(&message["params"], event_id);
+                false
+            }
+            Some("notifications/initialized") => {
+                session.mark_initialized();
+                false
+            }
+            Some("ping") => false,
+            Some(_) => request_id.is_some() && !session.is_initialized,
+            None => false,
+        };
+        drop(sessions);
+
+        if rejected {
+            tracing::warn!("Rejecting request from uninitialized session {}", actual_event.pubkey.to_hex());
+            let error = serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": request_id,
+                "error": {
+                    "code": -32600,
+                    "message": "Session not initialized: send `initialize` first",
+                },
+            });
+            self.send_response(&actual_event.pubkey, error.to_string(), &event_id).await?;
+            return Ok(None);
+        }
+
+        tracing::debug!("Received message from {}: {}", actual_event.pubkey.to_hex(), content);
+
+        if request_id.is_some() && message.get("method").is_some() {
+            metrics::global().requests_received.inc();
+            self.in_flight.lock().await.insert(event_id, Instant::now());
+        } else if message.get("method").is_some() {
+            metrics::global().notifications_received.inc();
+        }
+
+        Ok(Some(IncomingMessage {
+            content,
+            sender_pubkey: actual_event.pubkey,
+            event_id,
+            is_encrypted,
+        }))
+    }
+
+    /// Check a client against the allow/deny lists and its rate limit
+    async fn check_access(&self, client_pubkey: &PublicKey) -> Option<Refusal> {
+        let allowed = self
+            .config
+            .allowed_clients
+            .as_ref()
+            .is_none_or(|allowed| allowed.contains(client_pubkey));
+        if !allowed || self.config.denied_clients.contains(client_pubkey) {
+            return Some(Refusal::Denied);
+        }
+
+        let rate_limiter = self.rate_limiter.as_ref()?;
+        let checked = rate_limiter.lock().await.check(*client_pubkey, std::time::Instant::now());
+        checked.err().map(Refusal::RateLimited)
+    }
+
+    /// Answer a refused request with a JSON-RPC error; refused notifications and chunks
+    /// are dropped, and so are requests of a client answered within its retry window
+    ///
+    /// No session exists for refused clients, so the reply mirrors the encryption of
+    /// their message.
+    async fn refuse(
+        &self,
+        client_pubkey: &PublicKey,
+        content: &str,
+        request_event_id: &EventId,
+        is_encrypted: bool,
+        refusal: Refusal,
+    ) -> Result<()> {
+        tracing::warn!("Refusing message from {}: {}", client_pubkey.to_hex(), refusal);
+
+        let message: serde_json::Value = serde_json::from_str(content).unwrap_or_default();
+        let Some(id) = message.get("id").filter(|_| message.get("method").is_some()) else {
+            return Ok(());
+        };
+        let reply = self
+            .refusal_replies
+            .lock()
+            .await
+            .should_reply(*client_pubkey, refusal, std::time::Instant::now());
+        if !reply {
+            return Ok(());
+        }
 
+        let error = refusal.error_response(id.clone());
+        let builder = EventBuilder::new(Kind::from(CTXVM_MESSAGES_KIND), error.to_string())
+            .tag(Tag::public_key(*client_pubkey))
+            .tag(Tag::event(*request_event_id))
+            .tags(self.config.message_ttl.map(freshness::expiration_tag));
+        let use_encryption = self.config.encryption_mode.encrypt_for(is_encrypted);
+
+        self.publish_message(client_pubkey, builder, use_encryption).await?;
         Ok(())
     }
 
     /// Send a response to a client
+    ///
+    /// Encryption follows the configured mode; in `Optional` mode the reply mirrors the
+    /// encryption of the client's last message. Large responses are sent as chunks and
+    /// the ID of the last chunk event is returned.
     pub async fn send_response(
         &self,
         client_pubkey: &PublicKey,
         response_json: String,
         request_event_id: &EventId,
+    ) -> Result<EventId> {
+        self.record_negotiated_version(client_pubkey, &response_json, request_event_id).await;
+        let response_json = self.offload_blobs(client_pubkey, response_json).await;
+
+        let event_id = self.send_message(client_pubkey, response_json, Some(request_event_id)).await?;
+
+        metrics::global().responses_sent.inc();
+        if let Some(received) = self.in_flight.lock().await.remove(request_event_id) {
+            let latency = received.elapsed();
+            metrics::global().server_latency.observe(latency);
+            tracing::debug!(
+                peer = %client_pubkey,
+                request = %request_event_id,
+                latency_ms = latency.as_millis() as u64,
+                "Sent response"
+            );
+        }
+
+        Ok(event_id)
+    }
+
+    /// Move large binary payloads of a response to the blob store, if one is configured
+    /// and the client can download them
+    ///
+    /// Otherwise, or if uploading fails, the response is sent inline, chunked as needed.
+    async fn offload_blobs(&self, client_pubkey: &PublicKey, response_json: String) -> String {
+        let Some(store) = &self.config.blob_store else {
+            return response_json;
+        };
+        let accepts_blobs = self
+            .session(client_pubkey)
+            .await
+            .is_some_and(|session| session.accepts_blobs());
+        if !accepts_blobs {
+            return response_json;
+        }
+        let Ok(mut response) = serde_json::from_str::<serde_json::Value>(&response_json) else {
+            return response_json;
+        };
+
+        match blob::offload(store.as_ref(), &mut response, self.config.blob_threshold).await {
+            Ok(0) => response_json,
+            Ok(count) => {
+                tracing::debug!("Offloaded {} payloads to the blob store", count);
+                response.to_string()
+            }
+            Err(e) => {
+                tracing::warn!("Failed to offload payloads, sending them inline: {}", e);
+                response_json
+            }
+        }
+    }
+
+    /// Send a notification to a client
+    ///
+    /// Notifications about a request in progress (`notifications/progress`, logging
+    /// while handling it) pass `related_event_id` so the client can relate them to the
+    /// request; session-wide ones such as `notifications/tools/list_changed` pass `None`.
+    /// Encryption follows the same rules as [`Self::send_response`].
+    pub async fn send_notification(
+        &self,
+        client_pubkey: &PublicKey,
+        notification_json: String,
+        related_event_id: Option<&EventId>,
+    ) -> Result<EventId> {
+        let event_id = self.send_message(client_pubkey, notification_json, related_event_id).await?;
+        metrics::global().notifications_sent.inc();
+        Ok(event_id)
+    }
+
+    /// Send a message to a client, chunked if large, returning the last event ID
+    async fn send_message(
+        &self,
+        client_pubkey: &PublicKey,
+        json: String,
+        related_event_id: Option<&EventId>,
+    ) -> Result<EventId> {
+        let use_encryption = self.should_encrypt(client_pubkey).await;
+        let parts = chunking::split_message(&json, self.config.chunk_size)?;
+
+        let mut final_event_id = None;
+        for (content, chunk_tag) in parts {
+            let builder = EventBuilder::new(Kind::from(CTXVM_MESSAGES_KIND), content)
+                .tag(Tag::public_key(*client_pubkey))
+                .tags(related_event_id.map(|id| Tag::event(*id)))
+                .tags(chunk_tag)
+                .tags(self.config.message_ttl.map(freshness::expiration_tag));
+
+            final_event_id = Some(self.publish_message(client_pubkey, builder, use_encryption).await?);
+        }
+
+        final_event_id.ok_or_else(|| Error::InvalidMessage("Empty message".to_string()))
+    }
+
+    /// Publish a message to a client, gift wrapping it if requested
+    async fn publish_message(
+        &self,
+        client_pubkey: &PublicKey,
+        builder: EventBuilder,
         use_encryption: bool,
     ) -> Result<EventId> {
         let client = self.relay_pool.client();
+        let event = client.sign_event_builder(builder).await
+            .map_err(|e| Error::Other(e.to_string()))?;
 
-        let builder = EventBuilder::new(Kind::from(CTXVM_MESSAGES_KIND), response_json)
-            .tag(Tag::public_key(*client_pubkey))
-            .tag(Tag::event(*request_event_id));
-
-        let final_event_id = if use_encryption {
-            // Use client to send gift-wrapped
-            let event = client.sign_event_builder(builder).await
-                .map_err(|e| Error::Other(e.to_string()))?;
+        if use_encryption {
             // Convert to UnsignedEvent for gift wrapping
             let rumor = UnsignedEvent::new(
                 event.pubkey,
@@ -227,18 +911,45 @@ impl NostrServerTransport {
                 event.tags.clone(),
                 event.content.clone(),
             );
-            let output = client.as_ref()
-                .gift_wrap(client_pubkey, rumor, Vec::<Tag>::new())
+            self.relay_pool
+                .publish_gift_wrap(client_pubkey, rumor, self.config.message_ttl.map(freshness::expiration_tag), 0)
                 .await
-                .map_err(|e| Error::Encryption(e.to_string()))?;
-            output.val
         } else {
-            let output = client.send_event_builder(builder).await
-                .map_err(|e| Error::Transport(e.to_string()))?;
-            output.val
+            self.relay_pool.publish(event).await
+        }
+    }
+
+    /// Store the protocol version the server answered an `initialize` request with
+    async fn record_negotiated_version(&self, client_pubkey: &PublicKey, response_json: &str, request_event_id: &EventId) {
+        let mut sessions = self.sessions.write().await;
+        let Some(session) = sessions.get_mut(&client_pubkey.to_hex()) else {
+            return;
         };
+        if session.initialize_event_id.as_ref() != Some(request_event_id) {
+            return;
+        }
+
+        let response: serde_json::Value = serde_json::from_str(response_json).unwrap_or_default();
+        if let Some(version) = response["result"]["protocolVersion"].as_str() {
+            session.protocol_version = Some(version.to_string());
+        }
+    }
+
+    /// Session state of a client, if it has contacted this server
+    pub async fn session(&self, client_pubkey: &PublicKey) -> Option<ClientSession> {
+        self.sessions.read().await.get(&client_pubkey.to_hex()).cloned()
+    }
+
+    async fn should_encrypt(&self, client_pubkey: &PublicKey) -> bool {
+        let peer_encrypted = self
+            .sessions
+            .read()
+            .await
+            .get(&client_pubkey.to_hex())
+            .map(|session| session.is_encrypted)
+            .unwrap_or(false);
 
-        Ok(final_event_id)
+        self.config.encryption_mode.encrypt_for(peer_encrypted)
     }
 
     /// Clean up inactive sessions
@@ -247,5 +958,14 @@ impl NostrServerTransport {
         let timeout = self.config.session_timeout;
 
         sessions.retain(|_, session| session.last_activity.elapsed() < timeout);
+        drop(sessions);
+
+        // Requests the application never answered
+        self.in_flight.lock().await.retain(|_, received| received.elapsed() < timeout);
+
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.lock().await.prune(std::time::Instant::now());
+        }
+        self.refusal_replies.lock().await.prune(std::time::Instant::now());
     }
 }
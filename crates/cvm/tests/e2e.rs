@@ -0,0 +1,534 @@
+//! End-to-end transport tests against the in-process test relay
+//!
+//! Run with `cargo test -p cvm --features test-relay`.
+
+use cvm::testing::TestRelay;
+use cvm::metrics::DropReason;
+use cvm::{
+    DeliveryMode, EncryptionMode, Error, FileBlobStore, IncomingMessage, Keys, NostrClientTransport,
+    NostrClientTransportConfig, NostrServerTransport, NostrServerTransportConfig, RequestOptions, ServerInfo,
+};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+const WAIT: Duration = Duration::from_secs(5);
+
+async fn start_server(
+    relay: &TestRelay,
+    keys: &Keys,
+    encryption_mode: EncryptionMode,
+) -> (NostrServerTransport, mpsc::Receiver<IncomingMessage>) {
+    let config = NostrServerTransportConfig {
+        relay_urls: vec![relay.url()],
+        encryption_mode,
+        server_info: Some(ServerInfo {
+            name: Some("echo".to_string()),
+            version: Some("0.1.0".to_string()),
+            about: Some("Echoes tool arguments".to_string()),
+            ..Default::default()
+        }),
+        capabilities: json!({ "tools": {} }),
+        ..Default::default()
+    };
+    let server = NostrServerTransport::new(keys.clone(), config).await.unwrap();
+    let incoming = server.start().await.unwrap();
+    (server, incoming)
+}
+
+async fn connect_client(relay: &TestRelay, encryption_mode: EncryptionMode) -> NostrClientTransport {
+    let config = NostrClientTransportConfig {
+        relay_urls: vec![relay.url()],
+        encryption_mode,
+        request_timeout: WAIT,
+        ..Default::default()
+    };
+    let client = NostrClientTransport::new(Keys::generate(), config).await.unwrap();
+    client.connect().await.unwrap();
+    client
+}
+
+/// Answer every request with its method and params until the channel closes
+fn spawn_echo(server: NostrServerTransport, mut incoming: mpsc::Receiver<IncomingMessage>) {
+    tokio::spawn(async move {
+        while let Some(message) = incoming.recv().await {
+            let request: Value = serde_json::from_str(&message.content).unwrap();
+            let Some(id) = request.get("id").cloned() else {
+                continue;
+            };
+            let result = match request["method"].as_str() {
+                Some("initialize") => server.initialize_result(),
+                _ => json!({ "method": request["method"], "params": request["params"] }),
+            };
+            let response = json!({ "jsonrpc": "2.0", "id": id, "result": result });
+            server
+                .send_response(&message.sender_pubkey, response.to_string(), &message.event_id)
+                .await
+                .unwrap();
+        }
+    });
+}
+
+async fn request(client: &NostrClientTransport, server: &Keys, id: u64, method: &str, params: Value) -> Value {
+    let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+    let response = client
+        .send_request(&server.public_key(), request.to_string())
+        .await
+        .unwrap();
+    serde_json::from_str(&response).unwrap()
+}
+
+async fn handshake(client: &NostrClientTransport, server: &Keys) -> Value {
+    let initialize = request(
+        client,
+        server,
+        1,
+        "initialize",
+        json!({
+            "protocolVersion": cvm::constants::MCP_PROTOCOL_VERSION,
+            "capabilities": {},
+            "clientInfo": { "name": "e2e", "version": "0.1.0" },
+        }),
+    )
+    .await;
+
+    let initialized = json!({ "jsonrpc": "2.0", "method": "notifications/initialized" });
+    client
+        .send_notification(&server.public_key(), initialized.to_string())
+        .await
+        .unwrap();
+
+    initialize
+}
+
+#[tokio::test]
+async fn test_plaintext_request_response() {
+    let relay = TestRelay::start().await.unwrap();
+    let server_keys = Keys::generate();
+    let (server, incoming) = start_server(&relay, &server_keys, EncryptionMode::Disabled).await;
+    spawn_echo(server.clone(), incoming);
+
+    let client = connect_client(&relay, EncryptionMode::Disabled).await;
+    relay.wait_for_subscriptions(2, WAIT).await.unwrap();
+
+    let initialize = handshake(&client, &server_keys).await;
+    assert_eq!(initialize["result"]["serverInfo"]["name"], "echo");
+
+    let response = request(&client, &server_keys, 2, "tools/call", json!({ "name": "echo" })).await;
+    assert_eq!(response["id"], 2);
+    assert_eq!(response["result"]["method"], "tools/call");
+    assert_eq!(response["result"]["params"]["name"], "echo");
+}
+
+#[tokio::test]
+async fn test_gift_wrapped_request_response() {
+    let relay = TestRelay::start().await.unwrap();
+    let server_keys = Keys::generate();
+    let (server, incoming) = start_server(&relay, &server_keys, EncryptionMode::Required).await;
+    spawn_echo(server.clone(), incoming);
+
+    let client = connect_client(&relay, EncryptionMode::Required).await;
+    relay.wait_for_subscriptions(2, WAIT).await.unwrap();
+
+    handshake(&client, &server_keys).await;
+    let response = request(&client, &server_keys, 2, "tools/list", json!({})).await;
+    assert_eq!(response["result"]["method"], "tools/list");
+
+    // Gift wraps are regular events, so the relay keeps them; the plaintext never appears
+    let stored = relay.events().await;
+    assert!(!stored.is_empty());
+    assert!(stored.iter().all(|event| event.kind.as_u16() == cvm::constants::GIFT_WRAP_KIND));
+    assert!(stored.iter().all(|event| !event.content.contains("tools/list")));
+}
+
+#[tokio::test]
+async fn test_requests_before_initialize_are_rejected() {
+    let relay = TestRelay::start().await.unwrap();
+    let server_keys = Keys::generate();
+    let (server, incoming) = start_server(&relay, &server_keys, EncryptionMode::Optional).await;
+    spawn_echo(server.clone(), incoming);
+
+    let client = connect_client(&relay, EncryptionMode::Optional).await;
+    relay.wait_for_subscriptions(2, WAIT).await.unwrap();
+
+    let response = request(&client, &server_keys, 7, "tools/list", json!({})).await;
+    assert_eq!(response["id"], 7);
+    assert_eq!(response["error"]["code"], -32600);
+}
+
+#[tokio::test]
+async fn test_announcement_discovery() {
+    let relay = TestRelay::start().await.unwrap();
+    let server_keys = Keys::generate();
+    let (server, _incoming) = start_server(&relay, &server_keys, EncryptionMode::Optional).await;
+
+    server.announce().await.unwrap();
+    let tools = vec![json!({ "name": "echo", "inputSchema": { "type": "object" } })];
+    server.publish_tools(tools.clone()).await.unwrap();
+
+    let client = connect_client(&relay, EncryptionMode::Optional).await;
+
+    let announcement = client
+        .fetch_announcement(&server_keys.public_key())
+        .await
+        .unwrap()
+        .expect("announcement should be stored by the relay");
+    assert_eq!(announcement.pubkey, server_keys.public_key());
+    assert_eq!(announcement.server_info.name.as_deref(), Some("echo"));
+    assert!(announcement.supports_encryption);
+
+    assert_eq!(client.fetch_tools(&server_keys.public_key()).await.unwrap(), tools);
+}
+
+#[tokio::test]
+async fn test_retracted_announcement() {
+    let relay = TestRelay::start().await.unwrap();
+    let server_keys = Keys::generate();
+    let config = NostrServerTransportConfig {
+        relay_urls: vec![relay.url()],
+        announce_interval: Some(Duration::from_secs(60)),
+        ..Default::default()
+    };
+    let server = NostrServerTransport::new(server_keys.clone(), config).await.unwrap();
+    let _incoming = server.start().await.unwrap();
+
+    server.announce().await.unwrap();
+    server
+        .publish_tools(vec![json!({ "name": "echo", "inputSchema": { "type": "object" } })])
+        .await
+        .unwrap();
+
+    let client = connect_client(&relay, EncryptionMode::Optional).await;
+    let announcement = client
+        .fetch_announcement(&server_keys.public_key())
+        .await
+        .unwrap()
+        .expect("announcement should be stored by the relay");
+    assert_eq!(announcement.is_online(), Some(true));
+
+    server.retract().await.unwrap();
+
+    assert!(client.fetch_announcement(&server_keys.public_key()).await.unwrap().is_none());
+    assert!(client.fetch_tools(&server_keys.public_key()).await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_progress_notifications() {
+    let relay = TestRelay::start().await.unwrap();
+    let server_keys = Keys::generate();
+    let (server, mut incoming) = start_server(&relay, &server_keys, EncryptionMode::Optional).await;
+
+    // Report progress on every request before answering it
+    tokio::spawn(async move {
+        while let Some(message) = incoming.recv().await {
+            let request: Value = serde_json::from_str(&message.content).unwrap();
+            let Some(id) = request.get("id").cloned() else {
+                continue;
+            };
+            let result = match request["method"].as_str() {
+                Some("initialize") => server.initialize_result(),
+                _ => {
+                    let progress = json!({
+                        "jsonrpc": "2.0",
+                        "method": "notifications/progress",
+                        "params": { "progressToken": "t", "progress": 1, "total": 2 },
+                    });
+                    server
+                        .send_notification(&message.sender_pubkey, progress.to_string(), Some(&message.event_id))
+                        .await
+                        .unwrap();
+                    json!({})
+                }
+            };
+            let response = json!({ "jsonrpc": "2.0", "id": id, "result": result });
+            server
+                .send_response(&message.sender_pubkey, response.to_string(), &message.event_id)
+                .await
+                .unwrap();
+        }
+    });
+
+    let client = connect_client(&relay, EncryptionMode::Optional).await;
+    relay.wait_for_subscriptions(2, WAIT).await.unwrap();
+    let mut notifications = client.notifications();
+
+    handshake(&client, &server_keys).await;
+    let response = request(&client, &server_keys, 2, "tools/call", json!({ "name": "slow" })).await;
+    assert_eq!(response["id"], 2);
+
+    // The notification did not complete the request and arrives on its own stream
+    let notification = tokio::time::timeout(WAIT, notifications.recv()).await.unwrap().unwrap();
+    assert_eq!(notification.server_pubkey, server_keys.public_key());
+    assert!(notification.request_event_id.is_some());
+    let progress: Value = serde_json::from_str(&notification.content).unwrap();
+    assert_eq!(progress["params"]["progress"], 1);
+}
+
+#[tokio::test]
+async fn test_refused_clients_get_an_error() {
+    let relay = TestRelay::start().await.unwrap();
+    let server_keys = Keys::generate();
+    let config = NostrServerTransportConfig {
+        relay_urls: vec![relay.url()],
+        encryption_mode: EncryptionMode::Optional,
+        // An empty allowlist refuses everyone
+        allowed_clients: Some(Default::default()),
+        ..Default::default()
+    };
+    let server = NostrServerTransport::new(server_keys.clone(), config).await.unwrap();
+    let incoming = server.start().await.unwrap();
+    spawn_echo(server.clone(), incoming);
+
+    let client = connect_client(&relay, EncryptionMode::Optional).await;
+    relay.wait_for_subscriptions(2, WAIT).await.unwrap();
+
+    // Other tests share the counters, so only check that they moved
+    let metrics = cvm::metrics::global();
+    let sent = metrics.requests_sent.get();
+    let received = metrics.responses_received.get();
+    let refused = metrics.dropped(DropReason::Refused);
+
+    let response = request(&client, &server_keys, 1, "initialize", json!({})).await;
+    assert_eq!(response["id"], 1);
+    assert_eq!(response["error"]["code"], -32001);
+    assert!(metrics.requests_sent.get() > sent);
+    assert!(metrics.responses_received.get() > received);
+    assert!(metrics.dropped(DropReason::Refused) > refused);
+
+    // The refusal is not repeated within the retry window
+    let request = json!({ "jsonrpc": "2.0", "id": 2, "method": "initialize", "params": {} });
+    let result = client.send_request(&server_keys.public_key(), request.to_string()).await;
+    assert!(matches!(result, Err(Error::Timeout)));
+}
+
+#[tokio::test]
+async fn test_announced_pow_is_mined() {
+    let relay = TestRelay::start().await.unwrap();
+    let server_keys = Keys::generate();
+    let config = NostrServerTransportConfig {
+        relay_urls: vec![relay.url()],
+        encryption_mode: EncryptionMode::Optional,
+        server_info: Some(ServerInfo {
+            name: Some("mined".to_string()),
+            ..Default::default()
+        }),
+        min_pow: Some(8),
+        ..Default::default()
+    };
+    let server = NostrServerTransport::new(server_keys.clone(), config).await.unwrap();
+    server.announce().await.unwrap();
+    let incoming = server.start().await.unwrap();
+    spawn_echo(server.clone(), incoming);
+
+    for (i, encryption_mode) in [EncryptionMode::Disabled, EncryptionMode::Required].into_iter().enumerate() {
+        let client = connect_client(&relay, encryption_mode).await;
+        relay.wait_for_subscriptions(2 + i, WAIT).await.unwrap();
+        let announcement = client.fetch_announcement(&server_keys.public_key()).await.unwrap().unwrap();
+        assert_eq!(announcement.min_pow, Some(8));
+
+        let initialize = handshake(&client, &server_keys).await;
+        assert_eq!(initialize["result"]["serverInfo"]["name"], "mined");
+    }
+
+    // Plaintext messages are ephemeral; the stored gift wraps to the server must be mined
+    let requests: Vec<_> = relay
+        .events()
+        .await
+        .into_iter()
+        .filter(|event| event.kind.as_u16() == cvm::constants::GIFT_WRAP_KIND)
+        .filter(|event| event.tags.public_keys().any(|pubkey| *pubkey == server_keys.public_key()))
+        .collect();
+    assert!(!requests.is_empty());
+    assert!(requests.iter().all(|event| event.id.check_pow(8)));
+}
+
+#[tokio::test]
+async fn test_unmined_requests_are_dropped() {
+    let relay = TestRelay::start().await.unwrap();
+    let server_keys = Keys::generate();
+    let config = NostrServerTransportConfig {
+        relay_urls: vec![relay.url()],
+        encryption_mode: EncryptionMode::Optional,
+        min_pow: Some(8),
+        ..Default::default()
+    };
+    let server = NostrServerTransport::new(server_keys.clone(), config).await.unwrap();
+    let mut incoming = server.start().await.unwrap();
+
+    // Not announced yet, so the client has no difficulty to mine
+    let client = connect_client(&relay, EncryptionMode::Disabled).await;
+    relay.wait_for_subscriptions(2, WAIT).await.unwrap();
+    let metrics = cvm::metrics::global();
+    let dropped = metrics.dropped(DropReason::InsufficientPow);
+
+    let request = json!({ "jsonrpc": "2.0", "id": 1, "method": "tools/list" });
+    let result = client.send_request(&server_keys.public_key(), request.to_string()).await;
+    assert!(matches!(result, Err(Error::Timeout)));
+    assert!(metrics.dropped(DropReason::InsufficientPow) > dropped);
+    assert!(incoming.try_recv().is_err());
+
+    // The timeout makes the client read the announcement again before the next request
+    server.announce().await.unwrap();
+    spawn_echo(server.clone(), incoming);
+    handshake(&client, &server_keys).await;
+}
+
+#[tokio::test]
+async fn test_timed_out_requests_are_cancelled() {
+    let relay = TestRelay::start().await.unwrap();
+    let server_keys = Keys::generate();
+    let (server, mut incoming) = start_server(&relay, &server_keys, EncryptionMode::Optional).await;
+    let (seen, mut methods) = mpsc::unbounded_channel();
+
+    // Answer the handshake only; everything else is left hanging
+    tokio::spawn(async move {
+        while let Some(message) = incoming.recv().await {
+            let request: Value = serde_json::from_str(&message.content).unwrap();
+            if request["method"] == "initialize" {
+                let response = json!({ "jsonrpc": "2.0", "id": request["id"], "result": server.initialize_result() });
+                server
+                    .send_response(&message.sender_pubkey, response.to_string(), &message.event_id)
+                    .await
+                    .unwrap();
+            }
+            let _ = seen.send(request);
+        }
+    });
+
+    let client = connect_client(&relay, EncryptionMode::Optional).await;
+    relay.wait_for_subscriptions(2, WAIT).await.unwrap();
+    handshake(&client, &server_keys).await;
+
+    let server_pubkey = server_keys.public_key();
+    let options = RequestOptions::with_timeout(Duration::from_millis(500));
+    let slow = json!({ "jsonrpc": "2.0", "id": 2, "method": "tools/call", "params": { "name": "slow" } });
+    let handle = client.start_request(&server_pubkey, slow.to_string(), options.clone()).await.unwrap();
+
+    // Starting another request after the deadline evicts the first one before its timeout fires
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    let other = json!({ "jsonrpc": "2.0", "id": 3, "method": "tools/list" });
+    let _other = client.start_request(&server_pubkey, other.to_string(), options).await.unwrap();
+    assert!(matches!(handle.response().await, Err(Error::Timeout)));
+
+    let cancelled = tokio::time::timeout(WAIT, async {
+        while let Some(message) = methods.recv().await {
+            if message["method"] == "notifications/cancelled" && message["params"]["requestId"] == 2 {
+                return message;
+            }
+        }
+        panic!("server stopped");
+    })
+    .await
+    .expect("no cancellation reached the server");
+    assert_eq!(cancelled["params"]["reason"], "Request timed out");
+}
+
+#[tokio::test]
+async fn test_binary_results_are_offloaded() {
+    let relay = TestRelay::start().await.unwrap();
+    let server_keys = Keys::generate();
+    let blobs = std::env::temp_dir().join(format!("cvm-e2e-blobs-{}", std::process::id()));
+    let store = Arc::new(FileBlobStore::new(&blobs));
+    // "A" decodes to zero bytes, so this is 48KB of image data
+    let image = "A".repeat(64 * 1024);
+
+    let config = NostrServerTransportConfig {
+        relay_urls: vec![relay.url()],
+        blob_store: Some(store.clone()),
+        ..Default::default()
+    };
+    let server = NostrServerTransport::new(server_keys.clone(), config).await.unwrap();
+    let mut incoming = server.start().await.unwrap();
+
+    let result_image = image.clone();
+    tokio::spawn(async move {
+        while let Some(message) = incoming.recv().await {
+            let request: Value = serde_json::from_str(&message.content).unwrap();
+            let Some(id) = request.get("id").cloned() else {
+                continue;
+            };
+            let result = match request["method"].as_str() {
+                Some("initialize") => server.initialize_result(),
+                _ => json!({ "content": [{ "type": "image", "data": result_image, "mimeType": "image/png" }] }),
+            };
+            let response = json!({ "jsonrpc": "2.0", "id": id, "result": result });
+            server
+                .send_response(&message.sender_pubkey, response.to_string(), &message.event_id)
+                .await
+                .unwrap();
+        }
+    });
+
+    // A client without a blob store gets the image inline, in chunks
+    let client = connect_client(&relay, EncryptionMode::Optional).await;
+    relay.wait_for_subscriptions(2, WAIT).await.unwrap();
+    handshake(&client, &server_keys).await;
+    let response = request(&client, &server_keys, 2, "tools/call", json!({ "name": "draw" })).await;
+    let content = &response["result"]["content"][0];
+    assert_eq!(content["data"], image);
+    assert!(content.get("_meta").is_none());
+    assert!(!blobs.exists());
+
+    // One with a store advertises it and gets the image through the store
+    let config = NostrClientTransportConfig {
+        relay_urls: vec![relay.url()],
+        request_timeout: WAIT,
+        blob_store: Some(store),
+        ..Default::default()
+    };
+    let client = NostrClientTransport::new(Keys::generate(), config).await.unwrap();
+    client.connect().await.unwrap();
+    relay.wait_for_subscriptions(3, WAIT).await.unwrap();
+    handshake(&client, &server_keys).await;
+    let response = request(&client, &server_keys, 2, "tools/call", json!({ "name": "draw" })).await;
+    let content = &response["result"]["content"][0];
+    assert_eq!(content["data"], image);
+    assert!(content.get("_meta").is_none());
+    assert_eq!(std::fs::read_dir(&blobs).unwrap().count(), 1);
+
+    std::fs::remove_dir_all(&blobs).unwrap();
+}
+
+#[tokio::test]
+async fn test_queued_request_is_sent_after_reconnecting() {
+    let mut relay = TestRelay::start().await.unwrap();
+    let server_keys = Keys::generate();
+    let (server, incoming) = start_server(&relay, &server_keys, EncryptionMode::Optional).await;
+    spawn_echo(server.clone(), incoming);
+
+    let client = connect_client(&relay, EncryptionMode::Optional).await;
+    relay.wait_for_subscriptions(2, WAIT).await.unwrap();
+    handshake(&client, &server_keys).await;
+
+    relay.stop();
+
+    // Reconnecting waits for the SDK's retry interval, so allow for a few of them
+    let request = json!({ "jsonrpc": "2.0", "id": 2, "method": "tools/call", "params": { "name": "echo" } });
+    let options = RequestOptions {
+        timeout: Some(Duration::from_secs(90)),
+        delivery_mode: Some(DeliveryMode::Queued),
+        ..Default::default()
+    };
+    let sender = client.clone();
+    let server_pubkey = server_keys.public_key();
+    let response = tokio::spawn(async move {
+        sender.send_request_with(&server_pubkey, request.to_string(), options).await
+    });
+
+    tokio::time::timeout(Duration::from_secs(30), async {
+        while client.queued_messages().await == 0 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("request was not queued");
+
+    // Gift wraps are stored, so the server gets the request whenever it resubscribes
+    relay.restart().await.unwrap();
+
+    let response: Value = serde_json::from_str(&response.await.unwrap().unwrap()).unwrap();
+    assert_eq!(response["id"], 2);
+    assert_eq!(response["result"]["method"], "tools/call");
+    assert_eq!(client.queued_messages().await, 0);
+}
@@ -377,7 +377,7 @@ impl ImageGenerator for FluxGenerator {
         tracing::info!("Generating image: {}", config.prompt);
 
         // Validate dimensions
-        if config.width % 8 != 0 || config.height % 8 != 0 {
+        if !config.width.is_multiple_of(8) || !config.height.is_multiple_of(8) {
             return Err(ImageGenError::InvalidConfig(
                 "Width and height must be multiples of 8".into(),
             ));
@@ -389,7 +389,7 @@ impl ImageGenerator for FluxGenerator {
         }
 
         // Generate seed
-        let seed = config.seed.unwrap_or_else(|| rand::random());
+        let seed = config.seed.unwrap_or_else(rand::random);
         tracing::info!("Using seed: {}", seed);
 
         // Encode prompt
@@ -1,7 +1,8 @@
 // imagent - AI Image Generation Library
 // Provides interface for generating images using Flux and Stable Diffusion models via Candle
 
-// Full Flux implementation
+// Full Flux implementation (work in progress: not every helper is wired up yet)
+#[allow(dead_code)]
 mod flux_wip;
 
 // Stable Diffusion implementation
@@ -213,13 +213,13 @@ impl ImageGenerator for StableDiffusionGenerator {
         tracing::info!("Prompt: {}", config.prompt);
 
         // Validate dimensions
-        if config.width % 8 != 0 || config.height % 8 != 0 {
+        if !config.width.is_multiple_of(8) || !config.height.is_multiple_of(8) {
             return Err(ImageGenError::InvalidConfig(
                 "Width and height must be multiples of 8".into(),
             ));
         }
 
-        let seed = config.seed.unwrap_or_else(|| rand::random());
+        let seed = config.seed.unwrap_or_else(rand::random);
         tracing::info!("Using seed: {}", seed);
 
         // Get SD config
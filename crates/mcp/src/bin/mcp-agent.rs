@@ -2,7 +2,8 @@
 
 use clap::Parser;
 use mcp::config::MergedConfig;
-use mcp::{EncryptionMode, ServerInfo, NostrServerTransportConfig};
+use mcp::cvm::constants::DEFAULT_ANNOUNCE_INTERVAL_SECS;
+use mcp::{EncryptionMode, IncomingMessage, NostrServerTransport, NostrServerTransportConfig, ServerInfo};
 use mcp::gateway::Gateway;
 use mcp::signer;
 use nostr_sdk::nips::nip19::ToBech32;
@@ -103,12 +104,10 @@ async fn main() -> anyhow::Result<()> {
     let signer = if let Some(sk) = &config.nostr.private_key {
         signer::from_sk(sk)?
     } else {
-        let keys = signer::generate();
+        let (keys, keystore_dir) = mcp::config::generate_key(&agent_id)?;
         println!("\nGenerated new private key!");
         println!("Public key (npub): {}", keys.public_key().to_bech32()?);
-        println!("\nAdd this to your shared config file ({}):", args.shared_config.display());
-        println!("[keys]");
-        println!("{} = \"{}\"", agent_id, keys.secret_key().to_bech32()?);
+        println!("Stored as {} in keystore {}", agent_id, keystore_dir.display());
         println!();
         keys
     };
@@ -128,16 +127,27 @@ async fn main() -> anyhow::Result<()> {
     };
 
     // Create config
-    let transport_config = NostrServerTransportConfig {
+    let mut transport_config = NostrServerTransportConfig {
         relay_urls: config.nostr.relays.clone(),
+        auth_relay_urls: config.nostr.auth_relays.clone(),
         encryption_mode,
         server_info: Some(server_info),
+        capabilities: serde_json::json!({ "tools": {} }),
         session_timeout: Duration::from_secs(300),
+        // The agent runs until stopped, so keep its announcement fresh
+        announce_interval: Some(Duration::from_secs(DEFAULT_ANNOUNCE_INTERVAL_SECS)),
+        ..Default::default()
     };
+    config.access.apply(&mut transport_config)?;
 
     // Create gateway
     let gateway = Gateway::new(signer, transport_config).await?;
 
+    let _exporter = match config.agent.metrics_port {
+        Some(port) => Some(mcp::metrics::serve(([127, 0, 0, 1], port).into()).await?),
+        None => None,
+    };
+
     println!("Generating tools using Ollama...");
 
     // Generate tools based on subject using LLM
@@ -183,7 +193,86 @@ async fn main() -> anyhow::Result<()> {
     println!("Starting agent gateway...");
 
     // Start the gateway to listen for requests
-    gateway.start().await?;
+    let mut incoming = gateway.start().await?;
+
+    // Periodically report relay health; reconnection itself is automatic
+    let transport = gateway.transport().clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(300));
+        loop {
+            interval.tick().await;
+            for relay in transport.relay_status().await {
+                tracing::info!(
+                    "Relay {}: {} (latency: {:?}, last error: {})",
+                    relay.url,
+                    if relay.connected { "connected" } else { "disconnected" },
+                    relay.latency,
+                    relay.last_error.as_deref().unwrap_or("none")
+                );
+            }
+        }
+    });
+
+    let shutdown = tokio::signal::ctrl_c();
+    tokio::pin!(shutdown);
+
+    let server = gateway.transport().clone();
+    loop {
+        tokio::select! {
+            message = incoming.recv() => {
+                let Some(message) = message else {
+                    break;
+                };
+                handle_message(&server, message).await;
+            }
+            _ = &mut shutdown => break,
+        }
+    }
+
+    // Let clients know the agent is gone instead of leaving its announcement behind
+    println!("Shutting down, retracting announcement...");
+    drop(incoming);
+    gateway.retract().await?;
 
     Ok(())
 }
+
+/// Answer `initialize` and refuse every other request; notifications need no reply
+async fn handle_message(server: &NostrServerTransport, message: IncomingMessage) {
+    let request: serde_json::Value = serde_json::from_str(&message.content).unwrap_or_default();
+    let method = request["method"].as_str().unwrap_or_default();
+    let sender = message
+        .sender_pubkey
+        .to_bech32()
+        .unwrap_or_else(|_| message.sender_pubkey.to_hex());
+    tracing::info!(
+        "Received {} {} from {}",
+        if message.is_encrypted { "encrypted" } else { "plaintext" },
+        method,
+        sender
+    );
+    tracing::debug!("Message content: {}", message.content);
+
+    let Some(id) = request.get("id").cloned() else {
+        return;
+    };
+    let response = match method {
+        "initialize" => serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "result": server.initialize_result(),
+        }),
+        _ => serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": -32601, "message": format!("Method not found: {}", method) },
+        }),
+    };
+
+    if let Err(e) = server
+        .send_response(&message.sender_pubkey, response.to_string(), &message.event_id)
+        .await
+    {
+        tracing::warn!("Failed to answer {} from {}: {}", method, sender, e);
+    }
+}
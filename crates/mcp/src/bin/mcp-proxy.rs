@@ -0,0 +1,118 @@
+//! MCP Proxy binary - exposes a remote ContextVM server as a local stdio MCP server
+//!
+//! MCP hosts launch this like any stdio server; stdout carries only JSON-RPC, so all
+//! diagnostics go to stderr.
+
+use clap::Parser;
+use mcp::config::SharedConfig;
+#[cfg(feature = "http")]
+use mcp::http::{HttpFrontend, HttpFrontendConfig};
+use mcp::proxy::Proxy;
+use mcp::{signer, EncryptionMode, NostrClientTransportConfig, PublicKey};
+use std::path::PathBuf;
+use std::time::Duration;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// Server to proxy (npub or hex pubkey)
+    #[arg(long)]
+    server: String,
+
+    /// Path to shared configuration (default: crates/mcp/config.toml)
+    #[arg(long, default_value = "crates/mcp/config.toml")]
+    shared_config: PathBuf,
+
+    /// Nostr relay URLs (overrides config)
+    #[arg(long)]
+    relay: Vec<String>,
+
+    /// Nostr private key (nsec or hex format) (overrides config)
+    #[arg(long)]
+    private_key: Option<String>,
+
+    /// Encryption mode: optional, required, disabled (overrides config)
+    #[arg(long)]
+    encryption: Option<String>,
+
+    /// Request timeout in seconds
+    #[arg(long, default_value_t = 60)]
+    timeout: u64,
+
+    /// Serve streamable HTTP on this loopback address (e.g. 127.0.0.1:8808) instead of stdio
+    #[cfg(feature = "http")]
+    #[arg(long)]
+    http: Option<std::net::SocketAddr>,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    // Log to stderr: stdout belongs to the MCP host
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .with_writer(std::io::stderr)
+        .init();
+
+    let args = Args::parse();
+
+    // Load shared config
+    let shared_config = SharedConfig::from_file(&args.shared_config).unwrap_or_default();
+
+    // Get relay URLs (CLI overrides config)
+    let relay_urls = if !args.relay.is_empty() {
+        args.relay
+    } else {
+        shared_config.nostr.relays.clone()
+    };
+
+    // Parse encryption mode (CLI overrides config)
+    let encryption_mode_str = args.encryption.as_deref().unwrap_or(&shared_config.encryption.mode);
+    let encryption_mode = match encryption_mode_str {
+        "optional" => EncryptionMode::Optional,
+        "required" => EncryptionMode::Required,
+        "disabled" => EncryptionMode::Disabled,
+        _ => {
+            eprintln!("Invalid encryption mode: {}", encryption_mode_str);
+            std::process::exit(1);
+        }
+    };
+
+    let server_pubkey = PublicKey::parse(&args.server)?;
+
+    // Get private key (CLI > config > throwaway key)
+    let signer = match args.private_key.or_else(|| shared_config.get_key("user")) {
+        Some(sk) => signer::from_sk(&sk)?,
+        None => signer::generate(),
+    };
+
+    let config = NostrClientTransportConfig {
+        relay_urls,
+        auth_relay_urls: shared_config.nostr.auth_relays.clone(),
+        encryption_mode,
+        request_timeout: Duration::from_secs(args.timeout),
+        ..Default::default()
+    };
+
+    // Each HTTP session gets its own ephemeral key, so the signer is not used there
+    #[cfg(feature = "http")]
+    if let Some(bind) = args.http {
+        let frontend = HttpFrontend::new(HttpFrontendConfig {
+            bind,
+            ..HttpFrontendConfig::new(server_pubkey, config)
+        });
+        frontend.serve().await?;
+        return Ok(());
+    }
+
+    let proxy = Proxy::new(signer, config).await?;
+    proxy.connect().await?;
+
+    // Learn whether the server can decrypt before the first request
+    if let Err(e) = proxy.transport().fetch_announcement(&server_pubkey).await {
+        tracing::warn!("Could not fetch server announcement: {}", e);
+    }
+
+    proxy.serve_stdio(server_pubkey).await?;
+
+    Ok(())
+}
@@ -8,7 +8,7 @@ use crossterm::{
 };
 use mcp::config::SharedConfig;
 use mcp::cvm::constants::{SERVER_ANNOUNCEMENT_KIND, TOOLS_LIST_KIND};
-use mcp::{EncryptionMode, NostrClientTransportConfig};
+use mcp::{EncryptionMode, NostrClientTransportConfig, RelayPool, ServerAnnouncement};
 use mcp::proxy::Proxy;
 use mcp::signer;
 use nostr_sdk::prelude::*;
@@ -54,14 +54,27 @@ struct Args {
 struct DiscoveredAgent {
     pubkey: PublicKey,
     name: String,
-    _version: Option<String>,
     about: Option<String>,
     tools: Vec<serde_json::Value>,
+    /// Latest announcement, refreshed while the agent runs
+    announcement: ServerAnnouncement,
+}
+
+impl DiscoveredAgent {
+    fn status(&self) -> &'static str {
+        match self.announcement.is_online() {
+            Some(true) => "online",
+            Some(false) => "offline",
+            None => "unknown",
+        }
+    }
 }
 
 enum AppEvent {
-    AgentDiscovered(DiscoveredAgent),
+    AgentDiscovered(Box<DiscoveredAgent>),
+    AgentRetracted(PublicKey),
     ToolsDiscovered { pubkey: PublicKey, tools: Vec<serde_json::Value> },
+    Notification { pubkey: PublicKey, message: serde_json::Value },
     Quit,
 }
 
@@ -101,6 +114,19 @@ impl App {
     }
 
     fn handle_agent_discovered(&mut self, agent: DiscoveredAgent) {
+        // Refreshed announcements of known agents only update their status
+        if let Some(known) = self.discovered_agents.get_mut(&agent.pubkey) {
+            let was_offline = known.announcement.is_online() == Some(false);
+            known.name = agent.name;
+            known.about = agent.about;
+            known.announcement = agent.announcement;
+            if was_offline && known.announcement.is_online() == Some(true) {
+                let name = known.name.clone();
+                self.add_message(format!("🟢 {} is back online", name));
+            }
+            return;
+        }
+
         let name = agent.name.clone();
         let pubkey_npub = agent.pubkey.to_bech32().unwrap_or_else(|_| agent.pubkey.to_hex());
         self.discovered_agents.insert(agent.pubkey, agent);
@@ -111,8 +137,23 @@ impl App {
         ));
     }
 
+    fn handle_agent_retracted(&mut self, pubkey: PublicKey) {
+        let Some(agent) = self.discovered_agents.remove(&pubkey) else {
+            return;
+        };
+        self.add_message(format!("👋 {} shut down", agent.name));
+        if self.connected_agent == Some(pubkey) {
+            self.connected_agent = None;
+            self.add_message("Disconnected. Use /list and /connect".to_string());
+        }
+    }
+
     fn handle_tools_discovered(&mut self, pubkey: PublicKey, tools: Vec<serde_json::Value>) {
         if let Some(agent) = self.discovered_agents.get_mut(&pubkey) {
+            // Agents republish unchanged lists with their announcement
+            if agent.tools == tools {
+                return;
+            }
             let agent_name = agent.name.clone();
             agent.tools = tools.clone();
             self.add_message(format!(
@@ -123,6 +164,39 @@ impl App {
         }
     }
 
+    fn handle_notification(&mut self, pubkey: PublicKey, message: serde_json::Value) {
+        let agent_name = self
+            .discovered_agents
+            .get(&pubkey)
+            .map(|a| a.name.clone())
+            .unwrap_or_else(|| "Unknown".to_string());
+        let params = &message["params"];
+
+        match message["method"].as_str() {
+            Some("notifications/progress") => {
+                let progress = params["progress"].as_f64().unwrap_or_default();
+                let total = params["total"]
+                    .as_f64()
+                    .map(|total| format!("/{}", total))
+                    .unwrap_or_default();
+                let text = params["message"].as_str().unwrap_or("");
+                self.add_message(format!("⏳ [{}] {}{} {}", agent_name, progress, total, text));
+            }
+            Some("notifications/message") => {
+                let level = params["level"].as_str().unwrap_or("info");
+                let data = match &params["data"] {
+                    serde_json::Value::String(text) => text.clone(),
+                    data => data.to_string(),
+                };
+                self.add_message(format!("📝 [{}] {}: {}", agent_name, level, data));
+            }
+            Some(method) => {
+                self.add_message(format!("📝 [{}] {}", agent_name, method));
+            }
+            None => {}
+        }
+    }
+
     fn handle_command(&mut self, input: String) -> Option<AppEvent> {
         let input = input.trim();
 
@@ -148,7 +222,7 @@ impl App {
                         .enumerate()
                         .map(|(idx, agent)| {
                             let about = agent.about.as_deref().unwrap_or("No description");
-                            format!("  {}. {} - {}", idx + 1, agent.name, about)
+                            format!("  {}. {} [{}] - {}", idx + 1, agent.name, agent.status(), about)
                         })
                         .collect();
 
@@ -164,7 +238,7 @@ impl App {
                 // Try to parse as number (index)
                 if let Ok(idx) = arg.parse::<usize>() {
                     if idx == 0 || idx > self.discovered_agents.len() {
-                        self.add_message(format!("Invalid agent number. Use /list to see available agents."));
+                        self.add_message("Invalid agent number. Use /list to see available agents.".to_string());
                     } else {
                         let agent = self.discovered_agents.values().nth(idx - 1).unwrap();
                         self.connected_agent = Some(agent.pubkey);
@@ -258,6 +332,7 @@ async fn main() -> anyhow::Result<()> {
     } else {
         shared_config.nostr.relays.clone()
     };
+    let auth_relay_urls = shared_config.nostr.auth_relays.clone();
 
     // Parse encryption mode (CLI overrides config)
     let encryption_mode_str = args.encryption.as_deref().unwrap_or(&shared_config.encryption.mode);
@@ -279,20 +354,20 @@ async fn main() -> anyhow::Result<()> {
     let signer = if let Some(sk) = private_key {
         signer::from_sk(&sk)?
     } else {
-        let keys = signer::generate();
+        let (keys, keystore_dir) = mcp::config::generate_key("user")?;
         eprintln!("\nGenerated new private key for user!");
         eprintln!("Public key (npub): {}", keys.public_key().to_bech32()?);
-        eprintln!("\nAdd this to your shared config file ({}):", args.shared_config.display());
-        eprintln!("[keys]");
-        eprintln!("user = \"{}\"", keys.secret_key().to_bech32()?);
+        eprintln!("Stored as user in keystore {}", keystore_dir.display());
         eprintln!();
         keys
     };
 
     // Create config
     let config = NostrClientTransportConfig {
-        relay_urls,
+        relay_urls: relay_urls.clone(),
+        auth_relay_urls: auth_relay_urls.clone(),
         encryption_mode,
+        ..Default::default()
     };
 
     // Create and connect proxy
@@ -313,8 +388,32 @@ async fn main() -> anyhow::Result<()> {
     let (event_tx, mut event_rx) = mpsc::channel(100);
     let discovery_tx = event_tx.clone();
 
+    // Show server notifications (progress, logging) as they arrive
+    let mut notifications = proxy.transport().notifications();
+    let notification_tx = event_tx.clone();
     tokio::spawn(async move {
-        if let Err(e) = discover_agents(signer, discovery_tx).await {
+        loop {
+            match notifications.recv().await {
+                Ok(notification) => {
+                    let Ok(message) = serde_json::from_str(&notification.content) else {
+                        continue;
+                    };
+                    let event = AppEvent::Notification {
+                        pubkey: notification.server_pubkey,
+                        message,
+                    };
+                    if notification_tx.send(event).await.is_err() {
+                        break;
+                    }
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        if let Err(e) = discover_agents(signer, relay_urls, auth_relay_urls, discovery_tx).await {
             eprintln!("Discovery error: {}", e);
         }
     });
@@ -336,14 +435,13 @@ async fn main() -> anyhow::Result<()> {
 
 async fn discover_agents(
     signer: Keys,
+    relay_urls: Vec<String>,
+    auth_relay_urls: Vec<String>,
     event_tx: mpsc::Sender<AppEvent>,
 ) -> anyhow::Result<()> {
-    // Get relay pool client from signer
-    let client = Client::builder().signer(signer).build();
-
-    // Add and connect to relay
-    client.add_relay("wss://strfry.atlantislabs.space").await?;
-    client.connect().await;
+    let relay_pool = RelayPool::new(signer).await?;
+    relay_pool.require_auth(&auth_relay_urls).await?;
+    relay_pool.connect(&relay_urls).await?;
 
     // Subscribe to both server announcement and tools list events
     let filter = Filter::new().kinds(vec![
@@ -351,44 +449,47 @@ async fn discover_agents(
         Kind::from(TOOLS_LIST_KIND),
     ]);
 
-    client.subscribe(filter, None).await?;
+    let mut subscription = relay_pool.subscribe(vec![filter]).await?;
+
+    while let Some(event) = subscription.recv().await {
+        match event.kind.as_u16() {
+            SERVER_ANNOUNCEMENT_KIND => {
+                // Agents replace their announcement with an empty event on shutdown
+                if event.content.is_empty() {
+                    let _ = event_tx.send(AppEvent::AgentRetracted(event.pubkey)).await;
+                    continue;
+                }
 
-    let mut notifications = client.notifications();
+                // Parse server announcement
+                if let Some(announcement) = ServerAnnouncement::from_event(&event) {
+                    let agent = DiscoveredAgent {
+                        pubkey: announcement.pubkey,
+                        name: announcement
+                            .server_info
+                            .name
+                            .clone()
+                            .unwrap_or_else(|| "Unknown".to_string()),
+                        about: announcement.server_info.about.clone(),
+                        tools: Vec::new(), // Will be populated when tools list arrives
+                        announcement,
+                    };
 
-    while let Ok(notification) = notifications.recv().await {
-        if let RelayPoolNotification::Event { event, .. } = notification {
-            match event.kind.as_u16() {
-                SERVER_ANNOUNCEMENT_KIND => {
-                    // Parse server announcement
-                    if let Ok(server_info) = serde_json::from_str::<serde_json::Value>(&event.content) {
-                        let agent = DiscoveredAgent {
-                            pubkey: event.pubkey,
-                            name: server_info["name"]
-                                .as_str()
-                                .unwrap_or("Unknown")
-                                .to_string(),
-                            _version: server_info["version"].as_str().map(String::from),
-                            about: server_info["about"].as_str().map(String::from),
-                            tools: Vec::new(), // Will be populated when tools list arrives
-                        };
-
-                        let _ = event_tx.send(AppEvent::AgentDiscovered(agent)).await;
-                    }
+                    let _ = event_tx.send(AppEvent::AgentDiscovered(Box::new(agent))).await;
                 }
-                TOOLS_LIST_KIND => {
-                    // Parse tools list
-                    if let Ok(tools_data) = serde_json::from_str::<serde_json::Value>(&event.content) {
-                        if let Some(tools_array) = tools_data["tools"].as_array() {
-                            let tools: Vec<serde_json::Value> = tools_array.clone();
-                            let _ = event_tx.send(AppEvent::ToolsDiscovered {
-                                pubkey: event.pubkey,
-                                tools,
-                            }).await;
-                        }
+            }
+            TOOLS_LIST_KIND => {
+                // Parse tools list
+                if let Ok(tools_data) = serde_json::from_str::<serde_json::Value>(&event.content) {
+                    if let Some(tools_array) = tools_data["tools"].as_array() {
+                        let tools: Vec<serde_json::Value> = tools_array.clone();
+                        let _ = event_tx.send(AppEvent::ToolsDiscovered {
+                            pubkey: event.pubkey,
+                            tools,
+                        }).await;
                     }
                 }
-                _ => {}
             }
+            _ => {}
         }
     }
 
@@ -427,6 +528,8 @@ async fn run_app(
                         Style::default().fg(Color::Yellow)
                     } else if m.starts_with("🛠️") {
                         Style::default().fg(Color::Magenta)
+                    } else if m.starts_with("⏳") || m.starts_with("📝") {
+                        Style::default().fg(Color::Blue)
                     } else {
                         Style::default()
                     };
@@ -447,7 +550,7 @@ async fn run_app(
             let status = if let Some(pubkey) = &app.connected_agent {
                 app.discovered_agents
                     .get(pubkey)
-                    .map(|a| format!(" [Connected: {}]", a.name))
+                    .map(|a| format!(" [Connected: {} ({})]", a.name, a.status()))
                     .unwrap_or_else(|| " [Connected]".to_string())
             } else {
                 " [No agent connected]".to_string()
@@ -476,11 +579,17 @@ async fn run_app(
         if let Ok(app_event) = event_rx.try_recv() {
             match app_event {
                 AppEvent::AgentDiscovered(agent) => {
-                    app.handle_agent_discovered(agent);
+                    app.handle_agent_discovered(*agent);
+                }
+                AppEvent::AgentRetracted(pubkey) => {
+                    app.handle_agent_retracted(pubkey);
                 }
                 AppEvent::ToolsDiscovered { pubkey, tools } => {
                     app.handle_tools_discovered(pubkey, tools);
                 }
+                AppEvent::Notification { pubkey, message } => {
+                    app.handle_notification(pubkey, message);
+                }
                 AppEvent::Quit => return Ok(()),
             }
         }
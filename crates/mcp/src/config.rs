@@ -1,10 +1,12 @@
 //! Configuration file support for MCP agents
 
+use cvm::signer::keystore::{self, Keystore};
+use cvm::{Keys, NostrServerTransportConfig, PublicKey, RateLimit};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct SharedConfig {
     #[serde(default)]
     pub nostr: NostrConfig,
@@ -13,6 +15,8 @@ pub struct SharedConfig {
     #[serde(default)]
     pub encryption: EncryptionConfig,
     #[serde(default)]
+    pub access: AccessConfig,
+    #[serde(default)]
     pub keys: HashMap<String, String>,
 }
 
@@ -25,6 +29,8 @@ pub struct AgentConfig {
     pub ollama: Option<OllamaConfig>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub encryption: Option<EncryptionConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access: Option<AccessConfig>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -33,6 +39,7 @@ pub struct MergedConfig {
     pub nostr: NostrConfig,
     pub ollama: OllamaConfig,
     pub encryption: EncryptionConfig,
+    pub access: AccessConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -41,6 +48,9 @@ pub struct AgentInfo {
     pub subject: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub about: Option<String>,
+    /// Serve Prometheus metrics at `http://127.0.0.1:<port>/metrics`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics_port: Option<u16>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -52,6 +62,10 @@ pub struct NostrConfig {
     /// Nostr relay URLs
     #[serde(default = "default_relays")]
     pub relays: Vec<String>,
+
+    /// Relays among `relays` that require NIP-42 authentication
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub auth_relays: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -69,6 +83,46 @@ pub struct EncryptionConfig {
     pub mode: String, // "optional", "required", "disabled"
 }
 
+/// Which clients an agent serves, and how much
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AccessConfig {
+    /// Client pubkeys (npub or hex) allowed to connect; empty allows everyone not denied
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+
+    /// Client pubkeys (npub or hex) that are always refused
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny: Vec<String>,
+
+    /// Per-client message rate limit, e.g. `{ burst = 10, per_second = 0.5 }`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<RateLimit>,
+
+    /// Maximum number of concurrent client sessions
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_sessions: Option<usize>,
+}
+
+impl AccessConfig {
+    /// Apply the access rules to a server transport configuration
+    pub fn apply(&self, config: &mut NostrServerTransportConfig) -> anyhow::Result<()> {
+        let parse = |keys: &[String]| -> anyhow::Result<HashSet<PublicKey>> {
+            keys.iter()
+                .map(|key| PublicKey::parse(key).map_err(|e| anyhow::anyhow!("Invalid client pubkey {}: {}", key, e)))
+                .collect()
+        };
+
+        if !self.allow.is_empty() {
+            config.allowed_clients = Some(parse(&self.allow)?);
+        }
+        config.denied_clients = parse(&self.deny)?;
+        config.rate_limit = self.rate_limit;
+        config.max_sessions = self.max_sessions;
+
+        Ok(())
+    }
+}
+
 // Defaults
 
 fn default_relays() -> Vec<String> {
@@ -120,20 +174,35 @@ impl SharedConfig {
     }
 
     /// Get private key for a specific agent or user
+    ///
+    /// Falls back to a key named `agent_id` in the default keystore, returned as a
+    /// `keystore:<agent_id>` reference that [`cvm::signer::from_sk`] resolves.
     pub fn get_key(&self, agent_id: &str) -> Option<String> {
-        self.keys.get(agent_id).filter(|k| !k.is_empty()).cloned()
+        self.keys
+            .get(agent_id)
+            .filter(|k| !k.is_empty())
+            .cloned()
+            .or_else(|| {
+                Keystore::open_default()
+                    .ok()
+                    .filter(|keystore| keystore.contains(agent_id))
+                    .map(|_| format!("{}{}", keystore::KEYSTORE_PREFIX, agent_id))
+            })
     }
 }
 
-impl Default for SharedConfig {
-    fn default() -> Self {
-        Self {
-            nostr: NostrConfig::default(),
-            ollama: OllamaConfig::default(),
-            encryption: EncryptionConfig::default(),
-            keys: HashMap::new(),
-        }
-    }
+/// Generate a key for `agent_id` and store it in the default keystore
+///
+/// The key is encrypted with `$CVM_KEY_PASSPHRASE` if set. Returns the keystore
+/// directory along with the keys; the secret is never printed.
+pub fn generate_key(agent_id: &str) -> anyhow::Result<(Keys, std::path::PathBuf)> {
+    let keystore = Keystore::open_default()?;
+    let passphrase = std::env::var(keystore::PASSPHRASE_ENV).ok();
+
+    keystore.generate(agent_id, passphrase.as_deref())?;
+    let keys = keystore.load(agent_id, passphrase.as_deref())?;
+
+    Ok((keys, keystore.dir().to_path_buf()))
 }
 
 impl AgentConfig {
@@ -174,6 +243,7 @@ impl MergedConfig {
             nostr,
             ollama: agent_config.ollama.unwrap_or_else(|| shared.ollama.clone()),
             encryption: agent_config.encryption.unwrap_or_else(|| shared.encryption.clone()),
+            access: agent_config.access.unwrap_or_else(|| shared.access.clone()),
         })
     }
 }
@@ -183,6 +253,7 @@ impl Default for NostrConfig {
         Self {
             private_key: None,
             relays: default_relays(),
+            auth_relays: Vec::new(),
         }
     }
 }
@@ -0,0 +1,178 @@
+//! Request routing between Nostr clients and a bridged MCP server
+//!
+//! Several clients share one MCP server, so request IDs are rewritten to gateway IDs
+//! before reaching the server and restored on the way back. Progress tokens are
+//! rewritten the same way so progress notifications find their client.
+
+use cvm::IncomingMessage;
+use nostr_sdk::prelude::{EventId, PublicKey};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+/// Client request waiting for the MCP server
+#[derive(Debug, Clone)]
+pub(crate) struct Route {
+    pub client: PublicKey,
+    pub request_id: Value,
+    /// Event the response must reference
+    pub event_id: EventId,
+    pub progress_token: Option<Value>,
+}
+
+/// Request sent to the MCP server, by whom it was made
+#[derive(Debug)]
+pub(crate) enum Pending {
+    Client(Route),
+    /// `tools/list` issued by the gateway, with the tools of earlier pages
+    ListTools(Vec<Value>),
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct Router {
+    next_id: u64,
+    pending: HashMap<u64, Pending>,
+}
+
+impl Router {
+    fn allocate(&mut self, pending: Pending) -> u64 {
+        self.next_id += 1;
+        self.pending.insert(self.next_id, pending);
+        self.next_id
+    }
+
+    /// Rewrite a client request for the MCP server
+    pub(crate) fn client_request(&mut self, message: &IncomingMessage, mut request: Value) -> Value {
+        let progress_token = request
+            .pointer("/params/_meta/progressToken")
+            .cloned();
+
+        let id = self.allocate(Pending::Client(Route {
+            client: message.sender_pubkey,
+            request_id: request["id"].clone(),
+            event_id: message.event_id,
+            progress_token: progress_token.clone(),
+        }));
+
+        request["id"] = json!(id);
+        if progress_token.is_some() {
+            request["params"]["_meta"]["progressToken"] = json!(id);
+        }
+
+        request
+    }
+
+    /// `tools/list` request continuing from `cursor`
+    pub(crate) fn list_tools(&mut self, cursor: Option<Value>, tools: Vec<Value>) -> Value {
+        let id = self.allocate(Pending::ListTools(tools));
+
+        let mut params = json!({});
+        if let Some(cursor) = cursor {
+            params["cursor"] = cursor;
+        }
+
+        json!({ "jsonrpc": "2.0", "id": id, "method": "tools/list", "params": params })
+    }
+
+    /// Rewrite a client's `notifications/cancelled`, `None` if the request is unknown
+    pub(crate) fn cancel(&mut self, client: &PublicKey, mut notification: Value) -> Option<Value> {
+        let request_id = notification.pointer("/params/requestId")?;
+
+        let id = self.pending.iter().find_map(|(id, pending)| match pending {
+            Pending::Client(route) if route.client == *client && route.request_id == *request_id => {
+                Some(*id)
+            }
+            _ => None,
+        })?;
+
+        // The server may not answer a cancelled request, so forget it now
+        self.pending.remove(&id);
+        notification["params"]["requestId"] = json!(id);
+
+        Some(notification)
+    }
+
+    /// Match a response from the MCP server to its request, restoring the client's ID
+    pub(crate) fn server_response(&mut self, mut response: Value) -> Option<(Pending, Value)> {
+        let id = response.get("id")?.as_u64()?;
+        let pending = self.pending.remove(&id)?;
+
+        if let Pending::Client(route) = &pending {
+            response["id"] = route.request_id.clone();
+        }
+
+        Some((pending, response))
+    }
+
+    /// Route a progress notification to the client whose request it reports on
+    pub(crate) fn progress(&self, mut notification: Value) -> Option<(Route, Value)> {
+        let id = notification.pointer("/params/progressToken")?.as_u64()?;
+
+        let Some(Pending::Client(route)) = self.pending.get(&id) else {
+            return None;
+        };
+
+        notification["params"]["progressToken"] = route.progress_token.clone()?;
+
+        Some((route.clone(), notification))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_sdk::prelude::{EventBuilder, Keys, Kind};
+
+    fn incoming(keys: &Keys) -> IncomingMessage {
+        let event = EventBuilder::new(Kind::from(25910), "")
+            .sign_with_keys(keys)
+            .unwrap();
+
+        IncomingMessage {
+            content: String::new(),
+            sender_pubkey: keys.public_key(),
+            event_id: event.id,
+            is_encrypted: false,
+        }
+    }
+
+    #[test]
+    fn test_ids_are_rewritten_per_client() {
+        let (alice, bob) = (Keys::generate(), Keys::generate());
+        let mut router = Router::default();
+
+        // Both clients use ID 1 and the same progress token
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "tools/call",
+            "params": { "name": "slow", "_meta": { "progressToken": "p" } },
+        });
+        let from_alice = router.client_request(&incoming(&alice), request.clone());
+        let from_bob = router.client_request(&incoming(&bob), request);
+        assert_ne!(from_alice["id"], from_bob["id"]);
+        assert_eq!(from_bob["params"]["_meta"]["progressToken"], from_bob["id"]);
+
+        let progress = json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/progress",
+            "params": { "progressToken": from_bob["id"], "progress": 1 },
+        });
+        let (route, progress) = router.progress(progress).unwrap();
+        assert_eq!(route.client, bob.public_key());
+        assert_eq!(progress["params"]["progressToken"], "p");
+
+        let response = json!({ "jsonrpc": "2.0", "id": from_alice["id"], "result": {} });
+        let (pending, response) = router.server_response(response).unwrap();
+        assert!(matches!(pending, Pending::Client(route) if route.client == alice.public_key()));
+        assert_eq!(response["id"], 1);
+
+        let cancel = json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/cancelled",
+            "params": { "requestId": 1 },
+        });
+        assert!(router.cancel(&alice.public_key(), cancel.clone()).is_none());
+        let cancel = router.cancel(&bob.public_key(), cancel).unwrap();
+        assert_eq!(cancel["params"]["requestId"], from_bob["id"]);
+    }
+}
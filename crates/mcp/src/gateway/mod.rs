@@ -1,7 +1,19 @@
 //! Gateway module for exposing local MCP server over Nostr
 
+mod bridge;
+mod stdio;
+
+pub use stdio::StdioServerConfig;
+
 use crate::core::error::{Error, Result};
-use cvm::{NostrServerTransport, NostrServerTransportConfig, NostrSigner};
+use bridge::{Pending, Router};
+use cvm::constants::{DEFAULT_ANNOUNCE_INTERVAL_SECS, MCP_PROTOCOL_VERSION};
+use cvm::{IncomingMessage, NostrServerTransport, NostrServerTransportConfig, NostrSigner};
+use serde_json::{json, Value};
+use std::future::Future;
+use std::time::Duration;
+use stdio::StdioServer;
+use tokio::sync::mpsc;
 
 /// Gateway that bridges local MCP server to Nostr network
 pub struct Gateway {
@@ -10,10 +22,16 @@ pub struct Gateway {
 
 impl Gateway {
     /// Create a new gateway
+    ///
+    /// Gateways serve until shut down, so unless `config.announce_interval` is set their
+    /// announcement is refreshed every 10 minutes.
     pub async fn new(
         signer: impl NostrSigner + 'static,
-        config: NostrServerTransportConfig,
+        mut config: NostrServerTransportConfig,
     ) -> Result<Self> {
+        config
+            .announce_interval
+            .get_or_insert(Duration::from_secs(DEFAULT_ANNOUNCE_INTERVAL_SECS));
         let transport = NostrServerTransport::new(signer, config).await.map_err(Error::from)?;
 
         Ok(Self { transport })
@@ -29,14 +47,185 @@ impl Gateway {
         self.transport.publish_tools(tools).await.map_err(Error::from)
     }
 
+    /// Withdraw the announcement and tools list, e.g. on shutdown
+    pub async fn retract(&self) -> Result<()> {
+        self.transport.retract().await.map_err(Error::from)
+    }
+
     /// Start the gateway (also announces the server)
-    pub async fn start(&self) -> Result<()> {
+    ///
+    /// Returns the stream of incoming messages; reply to them via [`Gateway::transport`].
+    pub async fn start(&self) -> Result<mpsc::Receiver<IncomingMessage>> {
         // Announce server before starting to listen
         self.announce().await?;
 
         self.transport.start().await.map_err(Error::from)
     }
 
+    /// Serve a local MCP server over Nostr until it exits or `shutdown` completes
+    ///
+    /// The server is spawned from `server` and initialized by the gateway itself; its
+    /// `initialize` result is announced and replayed to every Nostr client that
+    /// initializes. Client requests are forwarded to its stdin and its responses and
+    /// progress notifications routed back to the requesting client. If the server has
+    /// tools, its `tools/list` is published and republished when it reports changes.
+    /// The announcement is retracted when serving stops.
+    ///
+    /// Signal handling is left to the caller, e.g. pass `tokio::signal::ctrl_c()` mapped
+    /// to `()`.
+    pub async fn serve_stdio(
+        &self,
+        server: StdioServerConfig,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<()> {
+        let mut child = StdioServer::spawn(&server)?;
+
+        let initialize_result = child
+            .request(
+                json!("gateway-initialize"),
+                "initialize",
+                json!({
+                    "protocolVersion": MCP_PROTOCOL_VERSION,
+                    "capabilities": {},
+                    "clientInfo": { "name": "mcp-gateway", "version": env!("CARGO_PKG_VERSION") },
+                }),
+            )
+            .await?;
+        child
+            .send(&json!({ "jsonrpc": "2.0", "method": "notifications/initialized" }))
+            .await?;
+        self.transport.set_initialize_result(initialize_result.clone());
+
+        let mut router = Router::default();
+        if initialize_result["capabilities"].get("tools").is_some() {
+            child.send(&router.list_tools(None, Vec::new())).await?;
+        }
+
+        let mut incoming = self.start().await?;
+
+        tokio::pin!(shutdown);
+
+        let result = loop {
+            tokio::select! {
+                message = incoming.recv() => {
+                    let Some(message) = message else {
+                        break Ok(());
+                    };
+                    if let Err(e) = self
+                        .handle_client_message(&mut child, &mut router, &initialize_result, message)
+                        .await
+                    {
+                        tracing::error!("Error forwarding client message: {}", e);
+                    }
+                }
+                message = child.recv() => {
+                    let message = match message {
+                        Ok(Some(message)) => message,
+                        Ok(None) => break Err(Error::Protocol("MCP server exited".to_string())),
+                        Err(e) => break Err(e),
+                    };
+                    if let Err(e) = self.handle_server_message(&mut child, &mut router, message).await {
+                        tracing::error!("Error forwarding MCP server message: {}", e);
+                    }
+                }
+                _ = &mut shutdown => break Ok(()),
+            }
+        };
+
+        // Stop listening before retracting, so no client is served after the retraction
+        drop(incoming);
+        child.shutdown().await;
+        if let Err(e) = self.retract().await {
+            tracing::warn!("Failed to retract announcement: {}", e);
+        }
+        result
+    }
+
+    async fn handle_client_message(
+        &self,
+        child: &mut StdioServer,
+        router: &mut Router,
+        initialize_result: &Value,
+        message: IncomingMessage,
+    ) -> Result<()> {
+        let request: Value = serde_json::from_str(&message.content)?;
+
+        match (request["method"].as_str(), request.get("id")) {
+            // The server is already initialized; answer from its cached result
+            (Some("initialize"), Some(id)) => {
+                let response = json!({ "jsonrpc": "2.0", "id": id, "result": initialize_result });
+                self.transport
+                    .send_response(&message.sender_pubkey, response.to_string(), &message.event_id)
+                    .await?;
+            }
+            (Some("notifications/initialized"), None) => {}
+            (Some("notifications/cancelled"), None) => {
+                if let Some(notification) = router.cancel(&message.sender_pubkey, request) {
+                    child.send(&notification).await?;
+                }
+            }
+            (Some(_), Some(_)) => {
+                let request = router.client_request(&message, request);
+                child.send(&request).await?;
+            }
+            (Some(_), None) => child.send(&request).await?,
+            // The gateway never forwards server requests, so clients have nothing to answer
+            (None, _) => tracing::debug!("Ignoring response from client {}", message.sender_pubkey),
+        }
+
+        Ok(())
+    }
+
+    async fn handle_server_message(
+        &self,
+        child: &mut StdioServer,
+        router: &mut Router,
+        message: Value,
+    ) -> Result<()> {
+        match (message["method"].as_str(), message.get("id")) {
+            (Some(_), Some(_)) => {
+                if let Some(reply) = stdio::local_reply(&message) {
+                    child.send(&reply).await?;
+                }
+            }
+            (Some("notifications/progress"), None) => {
+                if let Some((route, notification)) = router.progress(message) {
+                    self.transport
+                        .send_notification(&route.client, notification.to_string(), Some(&route.event_id))
+                        .await?;
+                }
+            }
+            (Some("notifications/tools/list_changed"), None) => {
+                child.send(&router.list_tools(None, Vec::new())).await?;
+            }
+            (Some(method), None) => tracing::debug!("Dropping MCP server notification {}", method),
+            (None, _) => match router.server_response(message) {
+                Some((Pending::Client(route), response)) => {
+                    self.transport
+                        .send_response(&route.client, response.to_string(), &route.event_id)
+                        .await?;
+                }
+                Some((Pending::ListTools(_), response)) if response.get("error").is_some() => {
+                    tracing::warn!("MCP server failed to list tools: {}", response["error"]);
+                }
+                Some((Pending::ListTools(mut tools), response)) => {
+                    let result = &response["result"];
+                    tools.extend(result["tools"].as_array().cloned().unwrap_or_default());
+
+                    match result.get("nextCursor").filter(|cursor| !cursor.is_null()) {
+                        Some(cursor) => {
+                            child.send(&router.list_tools(Some(cursor.clone()), tools)).await?;
+                        }
+                        None => self.publish_tools(tools).await?,
+                    }
+                }
+                None => tracing::debug!("Dropping unmatched MCP server response"),
+            },
+        }
+
+        Ok(())
+    }
+
     /// Get reference to the transport
     pub fn transport(&self) -> &NostrServerTransport {
         &self.transport
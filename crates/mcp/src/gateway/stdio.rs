@@ -0,0 +1,150 @@
+//! Child process speaking MCP JSON-RPC over stdio
+
+use crate::core::error::{Error, Result};
+use serde_json::Value;
+use std::process::Stdio;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+
+/// Command line of a local MCP server
+#[derive(Debug, Clone)]
+pub struct StdioServerConfig {
+    pub program: String,
+    pub args: Vec<String>,
+    /// Extra environment variables for the child
+    pub env: Vec<(String, String)>,
+}
+
+impl StdioServerConfig {
+    pub fn new(program: impl Into<String>, args: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            program: program.into(),
+            args: args.into_iter().map(Into::into).collect(),
+            env: Vec::new(),
+        }
+    }
+}
+
+/// Running MCP server with newline-delimited JSON-RPC on stdin/stdout
+///
+/// The child's stderr is inherited so its logs stay visible. The child is killed when
+/// this handle is dropped.
+pub(crate) struct StdioServer {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+}
+
+impl StdioServer {
+    pub(crate) fn spawn(config: &StdioServerConfig) -> Result<Self> {
+        let mut child = Command::new(&config.program)
+            .args(&config.args)
+            .envs(config.env.iter().cloned())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .kill_on_drop(true)
+            .spawn()?;
+
+        let stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| Error::Other("MCP server stdin unavailable".to_string()))?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| Error::Other("MCP server stdout unavailable".to_string()))?;
+
+        Ok(Self {
+            child,
+            stdin,
+            stdout: BufReader::new(stdout).lines(),
+        })
+    }
+
+    /// Write one message to the child
+    pub(crate) async fn send(&mut self, message: &Value) -> Result<()> {
+        let mut line = serde_json::to_string(message)?;
+        line.push('\n');
+        self.stdin.write_all(line.as_bytes()).await?;
+        self.stdin.flush().await?;
+        Ok(())
+    }
+
+    /// Read the next message, `None` once the child closed stdout
+    ///
+    /// Lines that are not JSON objects (stray prints) are logged and skipped.
+    pub(crate) async fn recv(&mut self) -> Result<Option<Value>> {
+        while let Some(line) = self.stdout.next_line().await? {
+            match serde_json::from_str::<Value>(&line) {
+                Ok(message) if message.is_object() => return Ok(Some(message)),
+                _ if line.trim().is_empty() => {}
+                _ => tracing::warn!("Ignoring non JSON-RPC output from MCP server: {}", line),
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Send a request and wait for its response, answering `ping`s meanwhile
+    ///
+    /// Only used before the bridge starts, when no other traffic is in flight.
+    pub(crate) async fn request(&mut self, id: Value, method: &str, params: Value) -> Result<Value> {
+        self.send(&serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        }))
+        .await?;
+
+        loop {
+            let message = self.recv().await?.ok_or_else(|| {
+                Error::Protocol(format!("MCP server exited before answering `{}`", method))
+            })?;
+
+            if message.get("method").is_some() {
+                if let Some(reply) = local_reply(&message) {
+                    self.send(&reply).await?;
+                }
+                continue;
+            }
+
+            if message.get("id") != Some(&id) {
+                continue;
+            }
+
+            if let Some(error) = message.get("error") {
+                return Err(Error::Protocol(format!("`{}` failed: {}", method, error)));
+            }
+            return Ok(message.get("result").cloned().unwrap_or_default());
+        }
+    }
+
+    /// Stop the child
+    pub(crate) async fn shutdown(mut self) {
+        if let Err(e) = self.child.kill().await {
+            tracing::debug!("Failed to kill MCP server: {}", e);
+        }
+    }
+}
+
+/// Reply the gateway gives itself to a request from the child
+///
+/// The child cannot address a particular Nostr client, so only `ping` is answered
+/// successfully; client features such as sampling are reported as unsupported.
+pub(crate) fn local_reply(request: &Value) -> Option<Value> {
+    let id = request.get("id")?;
+
+    Some(match request["method"].as_str() {
+        Some("ping") => serde_json::json!({ "jsonrpc": "2.0", "id": id, "result": {} }),
+        _ => serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": {
+                "code": -32601,
+                "message": "Method not supported by the Nostr gateway",
+            },
+        }),
+    })
+}
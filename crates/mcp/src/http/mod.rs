@@ -0,0 +1,432 @@
+//! Streamable HTTP front-end for remote ContextVM servers
+//!
+//! Serves the MCP streamable HTTP transport on a local address and relays it to one
+//! ContextVM server. Every HTTP session (`Mcp-Session-Id`) is backed by its own
+//! [`NostrClientTransport`] with a freshly generated key, so the Nostr server sees each
+//! session as an independent client.
+
+use crate::core::error::{Error, Result};
+use crate::proxy::host_notification;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, HeaderName, StatusCode};
+use axum::response::sse::{Event as SseEvent, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use cvm::{Keys, NostrClientTransport, NostrClientTransportConfig, PublicKey};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, RwLock};
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::StreamExt;
+
+/// Header carrying the session ID assigned on `initialize`
+pub const SESSION_HEADER: &str = "mcp-session-id";
+
+/// Capacity of each session's server-to-client stream
+const STREAM_CHANNEL_CAPACITY: usize = 100;
+
+/// HTTP front-end configuration
+#[derive(Debug, Clone)]
+pub struct HttpFrontendConfig {
+    /// Address to listen on; must be a loopback address
+    pub bind: SocketAddr,
+    /// Path of the MCP endpoint
+    pub path: String,
+    /// ContextVM server all sessions are relayed to
+    pub server_pubkey: PublicKey,
+    /// Transport settings used for every session
+    pub transport: NostrClientTransportConfig,
+    /// Sessions idle for longer than this are closed
+    pub session_timeout: Duration,
+    /// Open sessions allowed at once; further `initialize` requests get 503
+    pub max_sessions: usize,
+}
+
+impl HttpFrontendConfig {
+    pub fn new(server_pubkey: PublicKey, transport: NostrClientTransportConfig) -> Self {
+        Self {
+            bind: SocketAddr::from(([127, 0, 0, 1], 8808)),
+            path: "/mcp".to_string(),
+            server_pubkey,
+            transport,
+            session_timeout: Duration::from_secs(30 * 60),
+            max_sessions: 64,
+        }
+    }
+}
+
+/// One HTTP session and the Nostr client behind it
+struct HttpSession {
+    transport: NostrClientTransport,
+    /// Server messages not answering a POST, delivered on the GET stream
+    stream: broadcast::Sender<Value>,
+    last_activity: std::sync::Mutex<Instant>,
+}
+
+impl HttpSession {
+    fn touch(&self) {
+        if let Ok(mut last_activity) = self.last_activity.lock() {
+            *last_activity = Instant::now();
+        }
+    }
+
+    fn idle_for(&self) -> Duration {
+        self.last_activity
+            .lock()
+            .map(|last_activity| last_activity.elapsed())
+            .unwrap_or_default()
+    }
+}
+
+/// Local streamable HTTP endpoint relaying to a ContextVM server
+///
+/// Cloning is cheap and shares the sessions.
+#[derive(Clone)]
+pub struct HttpFrontend {
+    config: Arc<HttpFrontendConfig>,
+    sessions: Arc<RwLock<HashMap<String, Arc<HttpSession>>>>,
+}
+
+impl HttpFrontend {
+    pub fn new(config: HttpFrontendConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            sessions: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Router serving the MCP endpoint, for embedding into a larger server
+    pub fn router(&self) -> Router {
+        Router::new()
+            .route(
+                &self.config.path,
+                post(handle_post).get(handle_get).delete(handle_delete),
+            )
+            .with_state(self.clone())
+    }
+
+    /// Listen on the configured address until the server fails
+    ///
+    /// Sessions are unauthenticated, so only loopback addresses are accepted.
+    pub async fn serve(&self) -> Result<()> {
+        if !self.config.bind.ip().is_loopback() {
+            return Err(Error::Other(format!(
+                "Refusing to serve MCP over HTTP on {}: use a loopback address",
+                self.config.bind
+            )));
+        }
+
+        let listener = tokio::net::TcpListener::bind(self.config.bind).await?;
+        tracing::info!(
+            "Serving MCP over HTTP on http://{}{}",
+            listener.local_addr()?,
+            self.config.path
+        );
+
+        // Close idle sessions while serving
+        let reaper = self.clone();
+        let reaper_task = tokio::spawn(async move {
+            let period = (reaper.config.session_timeout / 2).max(Duration::from_secs(1));
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                reaper.cleanup_inactive_sessions().await;
+            }
+        });
+
+        let result = axum::serve(listener, self.router()).await;
+        reaper_task.abort();
+
+        result.map_err(Error::from)
+    }
+
+    /// Close sessions idle for longer than the session timeout
+    pub async fn cleanup_inactive_sessions(&self) {
+        let timeout = self.config.session_timeout;
+        let expired: Vec<Arc<HttpSession>> = {
+            let mut sessions = self.sessions.write().await;
+            let ids: Vec<String> = sessions
+                .iter()
+                .filter(|(_, session)| session.idle_for() > timeout)
+                .map(|(id, _)| id.clone())
+                .collect();
+            ids.iter().filter_map(|id| sessions.remove(id)).collect()
+        };
+
+        for session in expired {
+            if let Err(e) = session.transport.disconnect().await {
+                tracing::debug!("Failed to disconnect idle session: {}", e);
+            }
+        }
+    }
+
+    /// Look up the session named by the request headers
+    async fn session(&self, headers: &HeaderMap) -> std::result::Result<Arc<HttpSession>, (StatusCode, &'static str)> {
+        let Some(id) = headers.get(SESSION_HEADER).and_then(|id| id.to_str().ok()) else {
+            return Err((StatusCode::BAD_REQUEST, "Missing Mcp-Session-Id header"));
+        };
+
+        match self.sessions.read().await.get(id) {
+            Some(session) => {
+                session.touch();
+                Ok(session.clone())
+            }
+            None => Err((StatusCode::NOT_FOUND, "Unknown session")),
+        }
+    }
+
+    /// Start a session: a new Nostr client relays the `initialize` request
+    async fn initialize(&self, request: Value) -> Response {
+        let id = request["id"].clone();
+
+        if self.sessions.read().await.len() >= self.config.max_sessions {
+            return error_response(StatusCode::SERVICE_UNAVAILABLE, "Too many sessions");
+        }
+
+        let transport = match NostrClientTransport::new(Keys::generate(), self.config.transport.clone()).await {
+            Ok(transport) => transport,
+            Err(e) => return rpc_error(id, e.to_string()),
+        };
+        if let Err(e) = transport.connect().await {
+            let _ = transport.disconnect().await;
+            return rpc_error(id, e.to_string());
+        }
+
+        let server_pubkey = &self.config.server_pubkey;
+        if let Err(e) = transport.fetch_announcement(server_pubkey).await {
+            tracing::warn!("Could not fetch server announcement: {}", e);
+        }
+
+        let response = match transport.send_request(server_pubkey, request.to_string()).await {
+            Ok(response) => response,
+            Err(e) => {
+                let _ = transport.disconnect().await;
+                return rpc_error(id, e.to_string());
+            }
+        };
+
+        // Only a successful initialize opens a session
+        let body: Value = serde_json::from_str(&response).unwrap_or_default();
+        if body.get("result").is_none() {
+            let _ = transport.disconnect().await;
+            return Json(body).into_response();
+        }
+
+        // A fresh random key makes an unguessable session ID unrelated to the client key
+        let session_id = Keys::generate().public_key().to_hex();
+        let (stream, _) = broadcast::channel(STREAM_CHANNEL_CAPACITY);
+
+        // Feed server notifications into the session's GET stream; this ends once the
+        // transport is disconnected and dropped
+        let mut notifications = transport.notifications();
+        let session_stream = stream.clone();
+        let server_pubkey = *server_pubkey;
+        tokio::spawn(async move {
+            loop {
+                match notifications.recv().await {
+                    Ok(notification) if notification.server_pubkey == server_pubkey => {
+                        if let Some(message) = host_notification(&notification.content) {
+                            let _ = session_stream.send(message);
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        tracing::warn!("Dropped {} server notifications", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+        {
+            // Concurrent initializes may have filled the remaining slots meanwhile
+            let mut sessions = self.sessions.write().await;
+            if sessions.len() >= self.config.max_sessions {
+                drop(sessions);
+                let _ = transport.disconnect().await;
+                return error_response(StatusCode::SERVICE_UNAVAILABLE, "Too many sessions");
+            }
+            sessions.insert(
+                session_id.clone(),
+                Arc::new(HttpSession {
+                    transport,
+                    stream,
+                    last_activity: std::sync::Mutex::new(Instant::now()),
+                }),
+            );
+        }
+
+        tracing::info!("Started HTTP session {}", session_id);
+
+        (
+            StatusCode::OK,
+            [(HeaderName::from_static(SESSION_HEADER), session_id)],
+            Json(body),
+        )
+            .into_response()
+    }
+}
+
+async fn handle_post(State(frontend): State<HttpFrontend>, headers: HeaderMap, body: String) -> Response {
+    if !origin_allowed(&headers) {
+        return error_response(StatusCode::FORBIDDEN, "Origin not allowed");
+    }
+
+    let message: Value = match serde_json::from_str(&body) {
+        Ok(message) => message,
+        Err(e) => {
+            let error = json!({
+                "jsonrpc": "2.0",
+                "id": null,
+                "error": { "code": -32700, "message": format!("Parse error: {}", e) },
+            });
+            return (StatusCode::BAD_REQUEST, Json(error)).into_response();
+        }
+    };
+    if !message.is_object() {
+        return error_response(StatusCode::BAD_REQUEST, "Expected a single JSON-RPC message");
+    }
+
+    let method = message["method"].as_str();
+    if method == Some("initialize") && message.get("id").is_some() {
+        return frontend.initialize(message).await;
+    }
+
+    let session = match frontend.session(&headers).await {
+        Ok(session) => session,
+        Err((status, message)) => return error_response(status, message),
+    };
+    let server_pubkey = &frontend.config.server_pubkey;
+
+    match (method, message.get("id")) {
+        (Some(_), Some(id)) => {
+            match session.transport.send_request(server_pubkey, message.to_string()).await {
+                Ok(response) => {
+                    let body: Value = serde_json::from_str(&response).unwrap_or_default();
+                    Json(body).into_response()
+                }
+                Err(e) => rpc_error(id.clone(), e.to_string()),
+            }
+        }
+        (Some(_), None) => {
+            match session.transport.send_notification(server_pubkey, message.to_string()).await {
+                Ok(()) => StatusCode::ACCEPTED.into_response(),
+                Err(e) => error_response(StatusCode::BAD_GATEWAY, &e.to_string()),
+            }
+        }
+        // The server cannot send requests through the relay, so responses go nowhere
+        (None, _) => StatusCode::ACCEPTED.into_response(),
+    }
+}
+
+async fn handle_get(State(frontend): State<HttpFrontend>, headers: HeaderMap) -> Response {
+    if !origin_allowed(&headers) {
+        return error_response(StatusCode::FORBIDDEN, "Origin not allowed");
+    }
+
+    let accepts_sse = headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/event-stream"));
+    if !accepts_sse {
+        return error_response(StatusCode::METHOD_NOT_ALLOWED, "GET requires Accept: text/event-stream");
+    }
+
+    let session = match frontend.session(&headers).await {
+        Ok(session) => session,
+        Err((status, message)) => return error_response(status, message),
+    };
+
+    let stream = BroadcastStream::new(session.stream.subscribe()).filter_map(|message| {
+        message
+            .ok()
+            .map(|message| Ok::<_, std::convert::Infallible>(SseEvent::default().data(message.to_string())))
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default()).into_response()
+}
+
+async fn handle_delete(State(frontend): State<HttpFrontend>, headers: HeaderMap) -> Response {
+    if !origin_allowed(&headers) {
+        return error_response(StatusCode::FORBIDDEN, "Origin not allowed");
+    }
+
+    let Some(id) = headers.get(SESSION_HEADER).and_then(|id| id.to_str().ok()) else {
+        return error_response(StatusCode::BAD_REQUEST, "Missing Mcp-Session-Id header");
+    };
+
+    let Some(session) = frontend.sessions.write().await.remove(id) else {
+        return error_response(StatusCode::NOT_FOUND, "Unknown session");
+    };
+    if let Err(e) = session.transport.disconnect().await {
+        tracing::debug!("Failed to disconnect session {}: {}", id, e);
+    }
+
+    tracing::info!("Closed HTTP session {}", id);
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Reject browser requests from non-local origins (DNS rebinding protection)
+fn origin_allowed(headers: &HeaderMap) -> bool {
+    let Some(origin) = headers.get(header::ORIGIN) else {
+        return true;
+    };
+    let Ok(origin) = origin.to_str() else {
+        return false;
+    };
+
+    let authority = origin.split_once("://").map_or(origin, |(_, rest)| rest);
+    let host = match authority.strip_prefix('[') {
+        Some(ipv6) => ipv6.split(']').next().unwrap_or_default(),
+        None => authority.split(':').next().unwrap_or_default(),
+    };
+
+    matches!(host, "localhost" | "127.0.0.1" | "::1")
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
+
+fn rpc_error(id: Value, message: String) -> Response {
+    Json(json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": -32603, "message": message },
+    }))
+    .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn with_origin(origin: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::ORIGIN, HeaderValue::from_str(origin).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_origin_allowed() {
+        assert!(origin_allowed(&HeaderMap::new()));
+        assert!(origin_allowed(&with_origin("http://localhost:3000")));
+        assert!(origin_allowed(&with_origin("http://127.0.0.1")));
+        assert!(origin_allowed(&with_origin("http://[::1]:8080")));
+        assert!(!origin_allowed(&with_origin("https://evil.example")));
+        assert!(!origin_allowed(&with_origin("http://localhost.evil.example")));
+    }
+
+    #[tokio::test]
+    async fn test_serve_requires_loopback() {
+        let config = HttpFrontendConfig {
+            bind: SocketAddr::from(([0, 0, 0, 0], 0)),
+            ..HttpFrontendConfig::new(Keys::generate().public_key(), NostrClientTransportConfig::default())
+        };
+        assert!(HttpFrontend::new(config).serve().await.is_err());
+    }
+}
@@ -11,15 +11,18 @@ pub mod proxy;
 #[cfg(feature = "agent")]
 pub mod ollama;
 
+#[cfg(feature = "http")]
+pub mod http;
+
 // Re-export CVM types and modules
 pub use cvm::{
     self,
-    encryption, relay, signer, transport,
-    EncryptionMode, ServerInfo, ClientSession,
-    NostrClientTransport, NostrClientTransportConfig,
+    encryption, metrics, relay, signer, transport,
+    EncryptionMode, DeliveryMode, ServerInfo, ServerAnnouncement, ClientSession, RateLimit,
+    NostrClientTransport, NostrClientTransportConfig, RequestOptions, ServerNotification,
     NostrServerTransport, NostrServerTransportConfig,
     IncomingMessage,
-    RelayPool,
+    RelayHealth, RelayPool,
     Keys, NostrSigner, PublicKey,
 };
 
@@ -2,9 +2,18 @@
 
 use crate::core::error::{Error, Result};
 use crate::core::types::McpMessage;
-use cvm::{NostrClientTransport, NostrClientTransportConfig, NostrSigner, PublicKey};
+use cvm::{NostrClientTransport, NostrClientTransportConfig, NostrSigner, PublicKey, RequestOptions};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::{broadcast, Mutex};
+use tokio::task::JoinHandle;
 
 /// Proxy for accessing remote Nostr-based MCP servers
+///
+/// Cloning is cheap and shares the underlying transport.
+#[derive(Clone)]
 pub struct Proxy {
     transport: NostrClientTransport,
 }
@@ -26,17 +35,28 @@ impl Proxy {
     }
 
     /// Send a request to a remote server
+    ///
+    /// Encryption is decided by the transport's configured encryption mode.
     pub async fn request(
         &self,
         server_pubkey: &PublicKey,
         request: McpMessage,
-        use_encryption: bool,
+    ) -> Result<McpMessage> {
+        self.request_with(server_pubkey, request, RequestOptions::default()).await
+    }
+
+    /// Send a request to a remote server with custom timeout options
+    pub async fn request_with(
+        &self,
+        server_pubkey: &PublicKey,
+        request: McpMessage,
+        options: RequestOptions,
     ) -> Result<McpMessage> {
         // Convert McpMessage to JSON string
         let request_json = request.to_json()?;
 
         // Send via transport (which now works with JSON strings)
-        let response_json = self.transport.send_request(server_pubkey, request_json, use_encryption)
+        let response_json = self.transport.send_request_with(server_pubkey, request_json, options)
             .await
             .map_err(Error::from)?;
 
@@ -45,4 +65,155 @@ impl Proxy {
 
         Ok(response)
     }
+
+    /// Serve a remote server as a local stdio MCP server
+    ///
+    /// Reads newline-delimited JSON-RPC from stdin until EOF and writes responses to
+    /// stdout, so MCP hosts can launch the proxy like any local server.
+    pub async fn serve_stdio(&self, server_pubkey: PublicKey) -> Result<()> {
+        self.serve(server_pubkey, tokio::io::stdin(), tokio::io::stdout()).await
+    }
+
+    /// Forward JSON-RPC lines from `input` to a remote server, writing replies to `output`
+    ///
+    /// Requests run concurrently; a `notifications/cancelled` from the host aborts the
+    /// matching request and is forwarded to the server. Notifications from the server,
+    /// such as progress, are written to `output` as they arrive. Returns once `input` is
+    /// closed and outstanding requests have completed.
+    pub async fn serve<R, W>(&self, server_pubkey: PublicKey, input: R, output: W) -> Result<()>
+    where
+        R: tokio::io::AsyncRead + Unpin,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let output = Arc::new(Mutex::new(output));
+        let mut in_flight: HashMap<String, JoinHandle<()>> = HashMap::new();
+
+        // Relay server notifications such as progress to the host as they arrive
+        let mut notifications = self.transport.notifications();
+        let notification_output = output.clone();
+        let forwarder = tokio::spawn(async move {
+            loop {
+                match notifications.recv().await {
+                    Ok(notification) if notification.server_pubkey == server_pubkey => {
+                        let Some(message) = host_notification(&notification.content) else {
+                            continue;
+                        };
+                        if let Err(e) = write_line(&notification_output, &message).await {
+                            tracing::error!("Failed to write notification: {}", e);
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        tracing::warn!("Dropped {} server notifications", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+        let mut lines = BufReader::new(input).lines();
+
+        while let Some(line) = lines.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let message: Value = match serde_json::from_str(&line) {
+                Ok(message) => message,
+                Err(e) => {
+                    let error = json!({
+                        "jsonrpc": "2.0",
+                        "id": null,
+                        "error": { "code": -32700, "message": format!("Parse error: {}", e) },
+                    });
+                    write_line(&output, &error).await?;
+                    continue;
+                }
+            };
+
+            in_flight.retain(|_, task| !task.is_finished());
+
+            match (message["method"].as_str(), message.get("id").cloned()) {
+                (Some(_), Some(id)) => {
+                    let key = id_key(&id);
+                    let proxy = self.clone();
+                    let output = output.clone();
+                    let task = tokio::spawn(async move {
+                        let response = match proxy
+                            .request(&server_pubkey, McpMessage::Request(message))
+                            .await
+                        {
+                            Ok(McpMessage::Request(response))
+                            | Ok(McpMessage::Response(response))
+                            | Ok(McpMessage::Notification(response)) => response,
+                            Err(e) => json!({
+                                "jsonrpc": "2.0",
+                                "id": id,
+                                "error": { "code": -32603, "message": e.to_string() },
+                            }),
+                        };
+                        if let Err(e) = write_line(&output, &response).await {
+                            tracing::error!("Failed to write response: {}", e);
+                        }
+                    });
+                    in_flight.insert(key, task);
+                }
+                (Some(method), None) => {
+                    if method == "notifications/cancelled" {
+                        let request_id = message.pointer("/params/requestId").map(id_key);
+                        if let Some(task) = request_id.and_then(|id| in_flight.remove(&id)) {
+                            task.abort();
+                        }
+                    }
+                    // A lost notification is no reason to end the session
+                    if let Err(e) = self.transport.send_notification(&server_pubkey, message.to_string()).await {
+                        tracing::warn!("Failed to forward {} notification: {}", method, e);
+                    }
+                }
+                // Servers cannot reach the host through the proxy, so there is nothing to answer
+                (None, _) => tracing::debug!("Ignoring response from MCP host"),
+            }
+        }
+
+        // Input closed: let outstanding requests finish before returning
+        for (_, task) in in_flight {
+            let _ = task.await;
+        }
+        forwarder.abort();
+
+        Ok(())
+    }
+
+    /// Get reference to the transport
+    pub fn transport(&self) -> &NostrClientTransport {
+        &self.transport
+    }
+}
+
+/// Server message to pass on to a host, `None` for server requests
+///
+/// Requests (e.g. sampling) would need an answer routed back to the server, which the
+/// proxy cannot do, so only notifications are passed on.
+pub(crate) fn host_notification(content: &str) -> Option<Value> {
+    serde_json::from_str::<Value>(content)
+        .ok()
+        .filter(|message| message.get("id").is_none())
+}
+
+/// Key for a JSON-RPC request id; ids may be numbers or strings
+fn id_key(id: &Value) -> String {
+    id.to_string()
+}
+
+async fn write_line<W>(output: &Mutex<W>, message: &Value) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut line = serde_json::to_string(message)?;
+    line.push('\n');
+
+    let mut output = output.lock().await;
+    output.write_all(line.as_bytes()).await?;
+    output.flush().await?;
+
+    Ok(())
 }
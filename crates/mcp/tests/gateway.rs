@@ -0,0 +1,105 @@
+//! Gateway tests bridging a stdio MCP server through the in-process test relay
+//!
+//! Run with `cargo test -p mcp --features test-relay`.
+
+#![cfg(unix)]
+
+use mcp::cvm::testing::TestRelay;
+use mcp::gateway::{Gateway, StdioServerConfig};
+use mcp::{EncryptionMode, Keys, NostrClientTransport, NostrClientTransportConfig, NostrServerTransportConfig};
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+const WAIT: Duration = Duration::from_secs(5);
+
+/// MCP server with one `echo` tool, answering each request line with a canned result
+const SERVER_SCRIPT: &str = r#"
+while IFS= read -r line; do
+  id=$(printf '%s' "$line" | sed -n 's/.*"id":\([^,}]*\).*/\1/p')
+  case "$line" in
+    *'"method":"initialize"'*)
+      printf '{"jsonrpc":"2.0","id":%s,"result":{"protocolVersion":"2025-06-18","capabilities":{"tools":{}},"serverInfo":{"name":"script","version":"0.1.0"}}}\n' "$id" ;;
+    *'"method":"tools/list"'*)
+      printf '{"jsonrpc":"2.0","id":%s,"result":{"tools":[{"name":"echo","inputSchema":{"type":"object"}}]}}\n' "$id" ;;
+    *'"method":"tools/call"'*)
+      printf '{"jsonrpc":"2.0","id":%s,"result":{"content":[{"type":"text","text":"echoed"}]}}\n' "$id" ;;
+  esac
+done
+"#;
+
+async fn request(client: &NostrClientTransport, server: &Keys, id: u64, method: &str, params: Value) -> Value {
+    let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+    let response = client
+        .send_request(&server.public_key(), request.to_string())
+        .await
+        .unwrap();
+    serde_json::from_str(&response).unwrap()
+}
+
+#[tokio::test]
+async fn test_serve_stdio() {
+    let relay = TestRelay::start().await.unwrap();
+    let server_keys = Keys::generate();
+    let config = NostrServerTransportConfig {
+        relay_urls: vec![relay.url()],
+        encryption_mode: EncryptionMode::Optional,
+        ..Default::default()
+    };
+    let gateway = Gateway::new(server_keys.clone(), config).await.unwrap();
+
+    let (stop, stopped) = oneshot::channel::<()>();
+    let serving = tokio::spawn(async move {
+        let server = StdioServerConfig::new("sh", ["-c", SERVER_SCRIPT]);
+        gateway
+            .serve_stdio(server, async {
+                let _ = stopped.await;
+            })
+            .await
+    });
+
+    let config = NostrClientTransportConfig {
+        relay_urls: vec![relay.url()],
+        request_timeout: WAIT,
+        ..Default::default()
+    };
+    let client = NostrClientTransport::new(Keys::generate(), config).await.unwrap();
+    client.connect().await.unwrap();
+    relay.wait_for_subscriptions(2, WAIT).await.unwrap();
+
+    let initialize = request(
+        &client,
+        &server_keys,
+        1,
+        "initialize",
+        json!({
+            "protocolVersion": mcp::cvm::constants::MCP_PROTOCOL_VERSION,
+            "capabilities": {},
+            "clientInfo": { "name": "gateway-test", "version": "0.1.0" },
+        }),
+    )
+    .await;
+    assert_eq!(initialize["id"], 1);
+    assert_eq!(initialize["result"]["serverInfo"]["name"], "script");
+    let initialized = json!({ "jsonrpc": "2.0", "method": "notifications/initialized" });
+    client
+        .send_notification(&server_keys.public_key(), initialized.to_string())
+        .await
+        .unwrap();
+
+    let tools = request(&client, &server_keys, 2, "tools/list", json!({})).await;
+    assert_eq!(tools["id"], 2);
+    assert_eq!(tools["result"]["tools"][0]["name"], "echo");
+
+    let call = request(&client, &server_keys, 3, "tools/call", json!({ "name": "echo", "arguments": {} })).await;
+    assert_eq!(call["id"], 3);
+    assert_eq!(call["result"]["content"][0]["text"], "echoed");
+
+    // The gateway published the server's tools and retracts them when stopped
+    let published = client.fetch_tools(&server_keys.public_key()).await.unwrap();
+    assert_eq!(published[0]["name"], "echo");
+
+    stop.send(()).unwrap();
+    serving.await.unwrap().unwrap();
+    assert!(client.fetch_announcement(&server_keys.public_key()).await.unwrap().is_none());
+}
@@ -69,13 +69,12 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             let line_bytes = buffer.drain(..=newline_pos).collect::<Vec<_>>();
             if let Ok(line) = std::str::from_utf8(&line_bytes) {
                 let line = line.trim();
-                if !line.is_empty() {
-                    if let Ok(parsed) = serde_json::from_str::<StreamResponse>(line) {
+                if !line.is_empty()
+                    && let Ok(parsed) = serde_json::from_str::<StreamResponse>(line) {
                         full_response.push_str(&parsed.message.content);
                         print!("{}", parsed.message.content);
                         std::io::Write::flush(&mut std::io::stdout())?;
                     }
-                }
             }
         }
     }
@@ -161,18 +161,16 @@ fn should_ignore(path: &Path, patterns: &[String], watch_hidden: bool) -> bool {
 
     // Check if path contains hidden components
     if !watch_hidden {
-        if let Some(file_name) = path.file_name() {
-            if file_name.to_string_lossy().starts_with('.') {
+        if let Some(file_name) = path.file_name()
+            && file_name.to_string_lossy().starts_with('.') {
                 return true;
             }
-        }
         // Check parent directories for hidden components
         for component in path.components() {
-            if let Some(comp_str) = component.as_os_str().to_str() {
-                if comp_str.starts_with('.') && comp_str != "." {
+            if let Some(comp_str) = component.as_os_str().to_str()
+                && comp_str.starts_with('.') && comp_str != "." {
                     return true;
                 }
-            }
         }
     }
 
@@ -289,9 +287,9 @@ fn visit_dir(
 
         if path.is_dir() {
             visit_dir(&path, ignore_patterns, watch_hidden, latest_file)?;
-        } else if path.is_file() {
-            if let Ok(metadata) = fs::metadata(&path) {
-                if let Ok(modified) = metadata.modified() {
+        } else if path.is_file()
+            && let Ok(metadata) = fs::metadata(&path)
+                && let Ok(modified) = metadata.modified() {
                     if let Some((_, latest_time)) = latest_file {
                         if modified > *latest_time {
                             *latest_file = Some((path, modified));
@@ -300,8 +298,6 @@ fn visit_dir(
                         *latest_file = Some((path, modified));
                     }
                 }
-            }
-        }
     }
 
     Ok(())
@@ -332,7 +328,7 @@ async fn dispatch_to_orchestrator(
 
     // First, check if Ollama is responding
     println!("🔌 Checking Ollama connection...");
-    match client.get(&format!("{}/api/tags", config.ollama_endpoint)).send().await {
+    match client.get(format!("{}/api/tags", config.ollama_endpoint)).send().await {
         Ok(resp) => {
             if resp.status().is_success() {
                 println!("✅ Ollama is responding\n");
@@ -438,8 +434,8 @@ File Size: {} bytes"#,
             let line_bytes = buffer.drain(..=newline_pos).collect::<Vec<_>>();
             if let Ok(line) = std::str::from_utf8(&line_bytes) {
                 let line = line.trim();
-                if !line.is_empty() {
-                    if let Ok(parsed) = serde_json::from_str::<StreamResponse>(line) {
+                if !line.is_empty()
+                    && let Ok(parsed) = serde_json::from_str::<StreamResponse>(line) {
                         // Print content
                         if !parsed.message.content.is_empty() {
                             print!("{}", parsed.message.content);
@@ -451,7 +447,6 @@ File Size: {} bytes"#,
                             std::io::stdout().flush()?;
                         }
                     }
-                }
             }
         }
     }
@@ -61,7 +61,7 @@ impl AnalystExpert {
         for pattern in patterns {
             report.push_str(&format!("- {}\n", pattern));
         }
-        report.push_str("\n");
+        report.push('\n');
 
         // Recommendations
         report.push_str("## Recommendations\n\n");
@@ -98,7 +98,7 @@ impl ScribeExpert {
             for (key, value) in &content.metadata {
                 note.push_str(&format!("- **{}**: {}\n", key, value));
             }
-            note.push_str("\n");
+            note.push('\n');
         }
 
         // Add backlink section
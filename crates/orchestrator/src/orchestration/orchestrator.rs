@@ -17,9 +17,10 @@ pub struct Orchestrator {
 impl Orchestrator {
     /// Create a new orchestrator with the given model
     pub fn new(model: impl Into<String>) -> Self {
-        let mut config = OrchestratorConfig::default();
-        config.model = model.into();
-        Self::with_config(config)
+        Self::with_config(OrchestratorConfig {
+            model: model.into(),
+            ..Default::default()
+        })
     }
 
     /// Create orchestrator with custom configuration
@@ -129,31 +130,27 @@ impl Orchestrator {
         let content = content.trim();
 
         // Try to find JSON within code blocks
-        if let Some(start) = content.find("```json") {
-            if let Some(end) = content[start..].find("```") {
+        if let Some(start) = content.find("```json")
+            && let Some(end) = content[start..].find("```") {
                 let json_start = start + 7; // length of "```json"
                 let json_end = start + end;
                 return Ok(content[json_start..json_end].trim().to_string());
             }
-        }
 
         // Try to find JSON within plain code blocks
-        if let Some(start) = content.find("```") {
-            if let Some(end) = content[start + 3..].find("```") {
+        if let Some(start) = content.find("```")
+            && let Some(end) = content[start + 3..].find("```") {
                 let json_start = start + 3;
                 let json_end = start + 3 + end;
                 return Ok(content[json_start..json_end].trim().to_string());
             }
-        }
 
         // Try to find JSON object directly
-        if let Some(start) = content.find('{') {
-            if let Some(end) = content.rfind('}') {
-                if end > start {
+        if let Some(start) = content.find('{')
+            && let Some(end) = content.rfind('}')
+                && end > start {
                     return Ok(content[start..=end].to_string());
                 }
-            }
-        }
 
         // Return as-is if no JSON markers found
         Ok(content.to_string())
@@ -240,7 +237,7 @@ Respond ONLY with valid JSON, no other text."#,
             let expert = self
                 .registry
                 .get(*expert_type)
-                .ok_or_else(|| OrchestratorError::ExpertNotFound(*expert_type))?;
+                .ok_or(OrchestratorError::ExpertNotFound(*expert_type))?;
 
             let content_clone = content.clone();
             let expert_clone = expert.clone(); // Clone the Arc
@@ -280,7 +277,7 @@ Respond ONLY with valid JSON, no other text."#,
             let expert = self
                 .registry
                 .get(*expert_type)
-                .ok_or_else(|| OrchestratorError::ExpertNotFound(*expert_type))?;
+                .ok_or(OrchestratorError::ExpertNotFound(*expert_type))?;
 
             match expert.process(&current_content).await {
                 Ok(result) => {